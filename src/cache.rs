@@ -0,0 +1,172 @@
+use std::fs::{create_dir_all, read_dir, remove_file, rename, File};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::error::{Context, Result};
+use sha2::{Digest, Sha256};
+
+/// Tarballs are evicted least recently used first once the cache grows past this many bytes.
+pub const DEFAULT_LIMIT: u64 = 1024 * 1024 * 1024;
+
+/// Verified Zig tarballs stored as `<sha256>.tar.xz`.
+///
+/// Any other file dropped into the directory is hashed and adopted under its checksum the next
+/// time a lookup misses, so the cache can be seeded with tarballs fetched by other means.
+pub struct Cache {
+    dir: PathBuf,
+    limit: u64,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf, limit: u64) -> Self {
+        Cache { dir, limit }
+    }
+
+    /// Returns the cached tarball with the given checksum, if one is present.
+    pub fn get(&self, shasum: &str) -> Result<Option<PathBuf>> {
+        let path = self.path(shasum);
+        if !path.try_exists()? {
+            self.adopt()?;
+            if !path.try_exists()? {
+                return Ok(None);
+            }
+        }
+        if sha256(&path)? != shasum {
+            remove_file(&path)?;
+            return Ok(None);
+        }
+        File::options()
+            .append(true)
+            .open(&path)?
+            .set_modified(SystemTime::now())?;
+        Ok(Some(path))
+    }
+
+    /// Size of the cached tarball with the given checksum, without verifying it.
+    pub fn size(&self, shasum: &str) -> Option<u64> {
+        self.path(shasum).metadata().ok().map(|x| x.len())
+    }
+
+    /// Checksum of a tarball for `version` dropped into the cache under its original name, like
+    /// `zig-linux-x86_64-0.12.0.tar.xz`, so it can be installed without the download index.
+    pub fn seeded(&self, version: &str) -> Result<Option<String>> {
+        let entries = match read_dir(&self.dir) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            x => x?,
+        };
+        let suffix = format!("-{}.tar.xz", version);
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if entry.file_type()?.is_file() && name.starts_with("zig-") && name.ends_with(&suffix) {
+                return Ok(Some(sha256(&entry.path())?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Where a tarball with the given checksum should be downloaded to before calling [`Cache::commit`].
+    /// What an interrupted download left there is kept so it can be resumed.
+    pub fn part(&self, shasum: &str) -> Result<PathBuf> {
+        create_dir_all(&self.dir)
+            .with_context(|| format!("Cannot create cache directory {:?}", self.dir))?;
+        Ok(self.dir.join(format!("{}.tar.xz.part", shasum)))
+    }
+
+    /// Moves a verified download into the cache and evicts old tarballs to stay under the limit.
+    pub fn commit(&self, shasum: &str) -> Result<PathBuf> {
+        let path = self.path(shasum);
+        rename(self.dir.join(format!("{}.tar.xz.part", shasum)), &path)?;
+        self.evict(&path)?;
+        Ok(path)
+    }
+
+    fn path(&self, shasum: &str) -> PathBuf {
+        self.dir.join(format!("{}.tar.xz", shasum))
+    }
+
+    fn adopt(&self) -> Result<()> {
+        let entries = match read_dir(&self.dir) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            x => x?,
+        };
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if !entry.file_type()?.is_file() || is_entry(&name) || name.ends_with(".part") {
+                continue;
+            }
+            let shasum = sha256(&entry.path())?;
+            rename(entry.path(), self.path(&shasum))?;
+        }
+        Ok(())
+    }
+
+    fn evict(&self, keep: &Path) -> Result<()> {
+        let mut entries = Vec::new();
+        let mut total = 0;
+        for entry in read_dir(&self.dir)? {
+            let entry = entry?;
+            if !is_entry(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            total += metadata.len();
+            entries.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+        entries.sort();
+        for (_, len, path) in entries {
+            if total <= self.limit {
+                break;
+            }
+            if path != keep {
+                remove_file(&path)?;
+                total -= len;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_entry(name: &str) -> bool {
+    name.strip_suffix(".tar.xz").is_some_and(|x| {
+        x.len() == 64
+            && x.bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    })
+}
+
+pub fn sha256(file: &Path) -> Result<String> {
+    let mut file = File::open(file)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 4096];
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[test]
+fn it_cache() {
+    use std::fs::write;
+    use temp_dir::TempDir;
+
+    let temp = TempDir::new().unwrap();
+    let cache = Cache::new(temp.path().to_path_buf(), 8);
+    write(temp.child("zig-linux-x86_64-0.0.0.tar.xz"), "seeded").unwrap();
+    let seeded = sha256(&temp.child("zig-linux-x86_64-0.0.0.tar.xz")).unwrap();
+    assert_eq!(cache.seeded("0.0.0").unwrap(), Some(seeded.clone()));
+    assert_eq!(cache.seeded("0.0.1").unwrap(), None);
+    assert!(cache.get(&seeded).unwrap().is_some());
+
+    write(cache.part("f".repeat(64).as_str()).unwrap(), "download").unwrap();
+    cache.commit(&"f".repeat(64)).unwrap();
+    assert!(cache.get(&seeded).unwrap().is_none());
+}
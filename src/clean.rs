@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use semver::Version;
+
+use crate::installs::Install;
+
+/// Which of the unprotected installs `clean` removes. Every given condition has to hold.
+#[derive(Default)]
+pub struct Policy {
+    /// Spare the newest this many versions of every minor series, like 0.12.
+    pub keep_last: Option<usize>,
+    pub older_than: Option<Duration>,
+    pub unused_since: Option<Duration>,
+    /// Only remove `master` and development builds, which are otherwise never cleaned.
+    pub only_master_builds: bool,
+}
+
+fn is_master_build(name: &str) -> bool {
+    name == "master" || Version::parse(name).is_ok_and(|x| !x.pre.is_empty())
+}
+
+/// The installs to remove, given installs sorted by version and the names that must be spared.
+pub fn select(installs: Vec<Install>, protected: &[&str], policy: &Policy) -> Vec<Install> {
+    let now = SystemTime::now();
+    let age = |time: SystemTime| now.duration_since(time).unwrap_or(Duration::ZERO);
+
+    let mut newer_in_series = HashMap::new();
+    let mut spared = Vec::new();
+    for install in installs.iter().rev() {
+        if let Ok(x) = Version::parse(&install.name) {
+            let newer = newer_in_series.entry((x.major, x.minor)).or_insert(0);
+            if policy.keep_last.is_some_and(|n| *newer < n) {
+                spared.push(install.name.clone());
+            }
+            *newer += 1;
+        }
+    }
+
+    installs
+        .into_iter()
+        .filter(|x| !protected.contains(&x.name.as_str()) && !spared.contains(&x.name))
+        .filter(|x| {
+            if policy.only_master_builds {
+                is_master_build(&x.name)
+            } else {
+                x.name != "master"
+            }
+        })
+        .filter(|x| policy.older_than.is_none_or(|d| age(x.installed) > d))
+        .filter(|x| {
+            policy
+                .unused_since
+                .is_none_or(|d| age(x.last_used.unwrap_or(x.installed)) > d)
+        })
+        .collect()
+}
+
+#[test]
+fn it_clean_policy() {
+    use std::path::PathBuf;
+
+    let day = Duration::from_secs(86400);
+    let install = |name: &str, days: u32| Install {
+        name: name.to_string(),
+        path: PathBuf::from(name),
+        installed: SystemTime::now() - day * days,
+        last_used: None,
+    };
+    let installs = || {
+        vec![
+            install("0.11.0", 200),
+            install("0.12.0", 120),
+            install("0.12.1", 60),
+            install("0.13.0", 10),
+            install("master", 1),
+        ]
+    };
+    let names = |x: Vec<Install>| x.into_iter().map(|x| x.name).collect::<Vec<_>>();
+
+    let policy = Policy {
+        keep_last: Some(1),
+        ..Policy::default()
+    };
+    assert_eq!(names(select(installs(), &["0.13.0"], &policy)), ["0.12.0"]);
+
+    let policy = Policy {
+        older_than: Some(day * 90),
+        ..Policy::default()
+    };
+    assert_eq!(
+        names(select(installs(), &[], &policy)),
+        ["0.11.0", "0.12.0"]
+    );
+
+    let policy = Policy {
+        only_master_builds: true,
+        ..Policy::default()
+    };
+    assert_eq!(names(select(installs(), &[], &policy)), ["master"]);
+}
@@ -0,0 +1,275 @@
+use std::fs::{create_dir_all, read_to_string, write};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use crate::cache;
+use crate::error::{bail, Context, Error, ErrorKind, Result};
+use crate::global_cache::parse_size;
+
+pub const DROPINS: [&str; 8] = [
+    "ar", "cc", "c++", "dlltool", "lib", "ranlib", "objcopy", "rc",
+];
+
+/// Settings from `config.toml`, used by every command unless overridden on the command line.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    /// Where versions are installed. Overridden by `--install` and `ZMAN_HOME`.
+    pub install: Option<PathBuf>,
+    /// Where `zig` and drop-ins are linked. Overridden by `--link`.
+    pub link: Option<PathBuf>,
+    /// Zig subcommands linked as `zig-<name>` drop-ins.
+    pub dropins: Option<Vec<String>>,
+    /// Mirrors serving tarballs under their original file name, tried in order before ziglang.org.
+    pub mirrors: Vec<String>,
+    /// Size the tarball cache is kept under, like `2GiB`.
+    pub cache_limit: Option<String>,
+    /// Deduplicate files across versions after every install.
+    pub dedupe: bool,
+    /// Clone files with reflinks instead of hardlinking them when deduplicating.
+    pub reflink: bool,
+    /// Install missing versions on `zman run` instead of failing.
+    pub auto_install: bool,
+    /// Link `zig-<series>` like `zig-0.12` for every installed release series and master, with
+    /// versioned drop-ins like `zig-0.12-cc`.
+    pub versioned_links: bool,
+    /// Take write permission away from installed versions so they cannot be edited by accident.
+    pub read_only: bool,
+    /// `--older-than` of `zman cache prune` when neither option is given, like `30d`.
+    pub prune_older_than: Option<String>,
+    /// `--max-size` of `zman cache prune` when neither option is given, like `5GiB`.
+    pub prune_max_size: Option<String>,
+    /// Keys zman does not know, like misspelt ones. They are ignored rather than refused so that
+    /// `zman config` can still fix the file.
+    #[serde(skip)]
+    pub unknown: Vec<String>,
+}
+
+/// Keys accepted by `zman config`, along with whether they hold a list.
+pub const KEYS: [(&str, bool); 12] = [
+    ("install", false),
+    ("link", false),
+    ("dropins", true),
+    ("mirrors", true),
+    ("cache_limit", false),
+    ("dedupe", false),
+    ("reflink", false),
+    ("auto_install", false),
+    ("versioned_links", false),
+    ("read_only", false),
+    ("prune_older_than", false),
+    ("prune_max_size", false),
+];
+
+impl Config {
+    pub fn load(file: &Path) -> Result<Config> {
+        parse(read_table(file)?, file)
+    }
+
+    /// Like [`Config::load`], but leaves out values that cannot be used instead of failing, for
+    /// commands that repair the file. Returns why each was left out.
+    pub fn load_valid(file: &Path) -> (Config, Vec<String>) {
+        let table = match read_table(file) {
+            Ok(x) => x,
+            Err(e) => return (Config::default(), vec![describe(&e)]),
+        };
+        let mut ignored = Vec::new();
+        let valid = table
+            .into_iter()
+            .filter(|(key, value)| match check(key, value) {
+                Ok(()) => true,
+                Err(e) => {
+                    ignored.push(e.to_string());
+                    false
+                }
+            })
+            .collect();
+        (parse(valid, file).unwrap_or_default(), ignored)
+    }
+
+    pub fn dropins(&self) -> Vec<String> {
+        match &self.dropins {
+            Some(x) => x.clone(),
+            None => DROPINS.map(String::from).to_vec(),
+        }
+    }
+
+    pub fn cache_limit(&self) -> Result<u64> {
+        match &self.cache_limit {
+            Some(x) => parse_size(x),
+            None => Ok(cache::DEFAULT_LIMIT),
+        }
+    }
+
+    pub fn prune_older_than(&self) -> Result<Option<Duration>> {
+        self.prune_older_than
+            .as_deref()
+            .map(|x| {
+                humantime::parse_duration(x).map_err(|e| {
+                    Error::new(
+                        ErrorKind::Invalid,
+                        format!("Invalid duration {:?}: {}", x, e),
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    pub fn prune_max_size(&self) -> Result<Option<u64>> {
+        self.prune_max_size.as_deref().map(parse_size).transpose()
+    }
+}
+
+fn parse(table: Table, file: &Path) -> Result<Config> {
+    let unknown = table
+        .keys()
+        .filter(|x| is_list(x).is_err())
+        .cloned()
+        .collect();
+    let config: Config = table
+        .try_into()
+        .with_context(|| format!("Config file {:?} could not be parsed", file))?;
+    Ok(Config { unknown, ..config })
+}
+
+/// The message of an error followed by its cause, if any.
+fn describe(e: &Error) -> String {
+    match std::error::Error::source(e) {
+        Some(x) => format!("{}: {}", e, x),
+        None => e.to_string(),
+    }
+}
+
+/// Fails when `value` cannot be used for `key`. Unknown keys are accepted.
+fn check(key: &str, value: &Value) -> Result<()> {
+    let mut table = Table::new();
+    table.insert(key.to_string(), value.clone());
+    let check = || -> Result<()> {
+        let config: Config = table.try_into()?;
+        config.cache_limit()?;
+        config.prune_older_than()?;
+        config.prune_max_size()?;
+        Ok(())
+    };
+    if let Err(e) = check() {
+        bail!(Invalid, "Invalid value for {}: {}", key, e)
+    }
+    Ok(())
+}
+
+fn read_table(file: &Path) -> Result<Table> {
+    match read_to_string(file) {
+        Ok(x) => x
+            .parse()
+            .with_context(|| format!("Config file {:?} could not be parsed", file)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Table::new()),
+        Err(e) => Err(e).with_context(|| format!("Cannot read config file {:?}", file)),
+    }
+}
+
+fn is_list(key: &str) -> Result<bool> {
+    KEYS.iter()
+        .find(|(x, _)| *x == key)
+        .map(|(_, list)| *list)
+        .ok_or_else(|| Error::new(ErrorKind::Invalid, format!("Unknown config key {:?}", key)))
+}
+
+/// The value of a key as written in the config file, with lists separated by commas.
+pub fn get(file: &Path, key: &str) -> Result<Option<String>> {
+    is_list(key)?;
+    Ok(read_table(file)?.get(key).map(|x| match x {
+        Value::String(x) => x.clone(),
+        Value::Array(x) => x
+            .iter()
+            .map(|x| x.as_str().map_or_else(|| x.to_string(), String::from))
+            .collect::<Vec<_>>()
+            .join(","),
+        x => x.to_string(),
+    }))
+}
+
+/// Sets a key, splitting lists on commas, or removes it when `value` is `None`.
+pub fn set(file: &Path, key: &str, value: Option<&str>) -> Result<()> {
+    let list = is_list(key)?;
+    let mut table = read_table(file)?;
+    match value {
+        None => {
+            table.remove(key);
+        }
+        Some(x) if list => {
+            let items = x
+                .split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(|x| Value::String(x.to_string()));
+            table.insert(key.to_string(), Value::Array(items.collect()));
+        }
+        Some(x) => {
+            let value = match x {
+                "true" => Value::Boolean(true),
+                "false" => Value::Boolean(false),
+                x => Value::String(x.to_string()),
+            };
+            table.insert(key.to_string(), value);
+        }
+    }
+    // Only the changed key is checked, so one bad value cannot keep the others from being fixed
+    if let Some(x) = table.get(key) {
+        check(key, x)?;
+    }
+    if let Some(dir) = file.parent() {
+        create_dir_all(dir)?;
+    }
+    write(file, toml::to_string(&table)?)
+        .with_context(|| format!("Cannot write config file {:?}", file))
+}
+
+#[test]
+fn it_config() {
+    use temp_dir::TempDir;
+
+    let temp = TempDir::new().unwrap();
+    let file = temp.child("config.toml");
+    set(
+        &file,
+        "mirrors",
+        Some("https://a.example, https://b.example"),
+    )
+    .unwrap();
+    set(&file, "dedupe", Some("true")).unwrap();
+    assert!(set(&file, "dedupe", Some("maybe")).is_err());
+    assert!(set(&file, "cache_limit", Some("lots")).is_err());
+    assert!(set(&file, "colour", Some("blue")).is_err());
+    assert!(set(&file, "prune_older_than", Some("soon")).is_err());
+
+    let config = Config::load(&file).unwrap();
+    assert_eq!(config.mirrors.len(), 2);
+    assert!(config.dedupe);
+    assert_eq!(config.dropins().len(), DROPINS.len());
+    assert_eq!(
+        get(&file, "mirrors").unwrap().unwrap(),
+        "https://a.example,https://b.example"
+    );
+    set(&file, "mirrors", None).unwrap();
+    assert_eq!(get(&file, "mirrors").unwrap(), None);
+
+    // Unknown keys and bad values written by hand do not stop other keys from being changed
+    write(&file, "colour = \"blue\"\ndedupe = \"maybe\"\n").unwrap();
+    set(&file, "dedupe", Some("true")).unwrap();
+    let config = Config::load(&file).unwrap();
+    assert_eq!(config.unknown, ["colour"]);
+    assert!(config.dedupe);
+    write(
+        &file,
+        "dedupe = \"maybe\"\nmirrors = [\"https://a.example\"]\n",
+    )
+    .unwrap();
+    assert!(Config::load(&file).is_err());
+    let (config, ignored) = Config::load_valid(&file);
+    assert_eq!(config.mirrors.len(), 1);
+    assert_eq!(ignored.len(), 1);
+}
@@ -0,0 +1,280 @@
+use std::fs::{create_dir_all, read_to_string, write};
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use toml_edit::{value, DocumentMut, Item, Table};
+
+use crate::error::{bail, Context, Error, ErrorKind, Result};
+use crate::link::TargetDropins;
+
+/// Directory in the install directory holding generated build system files, hidden so it is not
+/// taken for an install.
+pub const DIR: &str = ".toolchains";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildSystem {
+    Cmake,
+    Meson,
+}
+
+/// A CMake toolchain file or Meson cross file for a target, regenerated whenever the default
+/// version changes. Its compilers are the target's drop-ins.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct CrossFile {
+    pub system: BuildSystem,
+    pub target: String,
+}
+
+impl CrossFile {
+    pub fn file_name(&self) -> &'static str {
+        match self.system {
+            BuildSystem::Cmake => "toolchain.cmake",
+            BuildSystem::Meson => "cross.ini",
+        }
+    }
+
+    /// Contents of the file, using `dropins` linked in `dir` for Zig `version`.
+    pub fn render(&self, dir: &Path, dropins: &TargetDropins, version: &str) -> Result<String> {
+        let (arch, os) = parse_target(&self.target)?;
+        let tool = |x: &str| dir.join(format!("{}-{}", self.target, x));
+        let header = format!(
+            "Generated by zman for Zig {}, rewritten when the default version changes",
+            version
+        );
+        Ok(match self.system {
+            BuildSystem::Cmake => format!(
+                "# {header}\n\
+                 set(CMAKE_SYSTEM_NAME {system})\n\
+                 set(CMAKE_SYSTEM_PROCESSOR {processor})\n\
+                 set(CMAKE_C_COMPILER {cc:?})\n\
+                 set(CMAKE_CXX_COMPILER {cxx:?})\n\
+                 set(CMAKE_AR {ar:?} CACHE FILEPATH \"Archiver\")\n\
+                 set(CMAKE_RANLIB {ranlib:?} CACHE FILEPATH \"Ranlib\")\n\
+                 # Zig brings its own libc, so there is no sysroot and nothing is searched on the host\n\
+                 set(CMAKE_FIND_ROOT_PATH_MODE_PROGRAM NEVER)\n\
+                 set(CMAKE_FIND_ROOT_PATH_MODE_LIBRARY ONLY)\n\
+                 set(CMAKE_FIND_ROOT_PATH_MODE_INCLUDE ONLY)\n\
+                 set(CMAKE_FIND_ROOT_PATH_MODE_PACKAGE ONLY)\n",
+                system = cmake_system(os),
+                processor = if arch == "x86" { "i686" } else { arch },
+                cc = tool("cc"),
+                cxx = tool("c++"),
+                ar = tool("ar"),
+                ranlib = tool("ranlib"),
+            ),
+            BuildSystem::Meson => format!(
+                "# {header}\n\
+                 [binaries]\n\
+                 c = {cc}\n\
+                 cpp = {cxx}\n\
+                 ar = {ar}\n\
+                 ranlib = {ranlib}\n\
+                 \n\
+                 [host_machine]\n\
+                 system = {system}\n\
+                 cpu_family = {family}\n\
+                 cpu = {cpu}\n\
+                 endian = {endian}\n",
+                cc = meson_string(&tool("cc").to_string_lossy()),
+                cxx = meson_string(&tool("c++").to_string_lossy()),
+                ar = meson_string(&tool("ar").to_string_lossy()),
+                ranlib = meson_string(&tool("ranlib").to_string_lossy()),
+                system = meson_string(meson_system(os)),
+                family = meson_string(meson_cpu_family(arch)),
+                cpu = meson_string(dropins.cpu.as_deref().unwrap_or(arch)),
+                endian = meson_string(endian(arch)),
+            ),
+        })
+    }
+}
+
+/// Splits a Zig target like `x86_64-linux-gnu.2.17` into its architecture and OS, dropping
+/// version suffixes.
+pub fn parse_target(target: &str) -> Result<(&str, &str)> {
+    // Drop-ins and cross files are named after the target, which must not lead out of their
+    // directory
+    if target.contains('/') || target.contains("..") {
+        bail!(
+            Invalid,
+            "Invalid target {:?}, it cannot contain / or ..",
+            target
+        )
+    }
+    let mut parts = target.split('-');
+    match (parts.next(), parts.next()) {
+        (Some(arch), Some(os)) if !arch.is_empty() && !os.is_empty() => {
+            Ok((arch, os.split('.').next().unwrap_or(os)))
+        }
+        _ => bail!(
+            Invalid,
+            "Invalid target {:?}, expected one like x86_64-linux-gnu",
+            target
+        ),
+    }
+}
+
+/// The Zig target and CPU matching a Rust target like `aarch64-unknown-linux-musl`.
+pub fn zig_target(rust_target: &str) -> Result<(String, Option<String>)> {
+    let parts: Vec<&str> = rust_target.split('-').collect();
+    let (arch, os, env) = match parts[..] {
+        [arch, "apple", "darwin"] => (arch, "macos", "none"),
+        [arch, "apple", "ios"] => (arch, "ios", "none"),
+        [arch, "wasi"] | [arch, "unknown", "wasi"] => (arch, "wasi", "musl"),
+        [arch, "wasip1"] | [arch, "unknown", "wasip1"] => (arch, "wasi", "musl"),
+        [arch, _, os, env] => (arch, os, env),
+        _ => bail!(
+            Invalid,
+            "Unsupported Rust target {:?}, expected one like x86_64-unknown-linux-gnu",
+            rust_target
+        ),
+    };
+    if env == "msvc" {
+        bail!(
+            Invalid,
+            "Zig cannot link for MSVC targets like {}, use the windows-gnu one instead",
+            rust_target
+        )
+    }
+    let (arch, cpu) = match arch {
+        "i586" | "i686" => ("x86", Some(arch)),
+        "armv7" => ("arm", Some("generic+v7a")),
+        "arm" => ("arm", None),
+        "riscv64gc" => ("riscv64", None),
+        "riscv32gc" | "riscv32imac" => ("riscv32", None),
+        arch => (arch, None),
+    };
+    Ok((format!("{}-{}-{}", arch, os, env), cpu.map(String::from)))
+}
+
+/// Points Cargo at `linker` and `ar` for `rust_target` in `file`, keeping the rest of it as is.
+pub fn write_cargo_config(file: &Path, rust_target: &str, linker: &Path, ar: &Path) -> Result<()> {
+    let mut doc = match read_to_string(file) {
+        Ok(x) => x.parse::<DocumentMut>().map_err(|e| {
+            Error::new(
+                ErrorKind::Invalid,
+                format!("{:?} could not be parsed: {}", file, e),
+            )
+        })?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => DocumentMut::new(),
+        Err(e) => return Err(e).with_context(|| format!("Cannot read {:?}", file)),
+    };
+    let targets = doc["target"].or_insert(implicit_table());
+    let target = targets[rust_target].or_insert(toml_edit::table());
+    target["linker"] = value(linker.to_string_lossy().as_ref());
+    target["ar"] = value(ar.to_string_lossy().as_ref());
+    if let Some(dir) = file.parent() {
+        create_dir_all(dir)?;
+    }
+    write(file, doc.to_string()).with_context(|| format!("Cannot write {:?}", file))
+}
+
+fn implicit_table() -> Item {
+    let mut table = Table::new();
+    table.set_implicit(true);
+    Item::Table(table)
+}
+
+fn cmake_system(os: &str) -> String {
+    match os {
+        "linux" => "Linux".to_string(),
+        "windows" => "Windows".to_string(),
+        "macos" => "Darwin".to_string(),
+        "freebsd" => "FreeBSD".to_string(),
+        "netbsd" => "NetBSD".to_string(),
+        "openbsd" => "OpenBSD".to_string(),
+        "wasi" => "WASI".to_string(),
+        "freestanding" => "Generic".to_string(),
+        os => {
+            let mut chars = os.chars();
+            chars.next().map_or_else(String::new, |x| {
+                x.to_uppercase().chain(chars).collect::<String>()
+            })
+        }
+    }
+}
+
+fn meson_string(x: &str) -> String {
+    format!("'{}'", x.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn meson_system(os: &str) -> &str {
+    match os {
+        "macos" => "darwin",
+        "freestanding" => "none",
+        os => os,
+    }
+}
+
+fn meson_cpu_family(arch: &str) -> &str {
+    match arch {
+        "arm" | "armeb" | "thumb" | "thumbeb" => "arm",
+        "aarch64" | "aarch64_be" => "aarch64",
+        "powerpc64" | "powerpc64le" => "ppc64",
+        "powerpc" | "powerpcle" => "ppc",
+        "mips" | "mipsel" => "mips",
+        "mips64" | "mips64el" => "mips64",
+        arch => arch,
+    }
+}
+
+fn endian(arch: &str) -> &str {
+    match arch {
+        "aarch64_be" | "armeb" | "thumbeb" | "mips" | "mips64" | "powerpc" | "powerpc64"
+        | "s390x" | "sparc" | "sparc64" => "big",
+        _ => "little",
+    }
+}
+
+#[test]
+fn it_cross_file() {
+    assert_eq!(
+        parse_target("x86_64-linux-gnu.2.17").unwrap(),
+        ("x86_64", "linux")
+    );
+    assert_eq!(
+        parse_target("aarch64-macos.13-none").unwrap(),
+        ("aarch64", "macos")
+    );
+    assert!(parse_target("x86_64").is_err());
+    assert!(parse_target("").is_err());
+    assert!(parse_target("../x").is_err());
+    assert!(parse_target("x86_64-linux/../../x").is_err());
+
+    assert_eq!(
+        zig_target("aarch64-unknown-linux-musl").unwrap(),
+        ("aarch64-linux-musl".to_string(), None)
+    );
+    assert_eq!(
+        zig_target("x86_64-apple-darwin").unwrap().0,
+        "x86_64-macos-none"
+    );
+    assert_eq!(
+        zig_target("armv7-unknown-linux-gnueabihf").unwrap(),
+        (
+            "arm-linux-gnueabihf".to_string(),
+            Some("generic+v7a".to_string())
+        )
+    );
+    assert_eq!(zig_target("wasm32-wasip1").unwrap().0, "wasm32-wasi-musl");
+    assert!(zig_target("x86_64").is_err());
+
+    let dropins = TargetDropins {
+        target: "aarch64-macos".to_string(),
+        cpu: None,
+        flags: Vec::new(),
+    };
+    let mut cross = CrossFile {
+        system: BuildSystem::Cmake,
+        target: dropins.target.clone(),
+    };
+    let cmake = cross.render(Path::new("/t"), &dropins, "0.13.0").unwrap();
+    assert!(cmake.contains("set(CMAKE_SYSTEM_NAME Darwin)"));
+    assert!(cmake.contains("set(CMAKE_C_COMPILER \"/t/aarch64-macos-cc\")"));
+    cross.system = BuildSystem::Meson;
+    let meson = cross.render(Path::new("/t"), &dropins, "0.13.0").unwrap();
+    assert!(meson.contains("c = '/t/aarch64-macos-cc'"));
+    assert!(meson.contains("system = 'darwin'"));
+    assert!(meson.contains("endian = 'little'"));
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{hard_link, read_dir, remove_file, rename};
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use indicatif::HumanBytes;
+use walkdir::WalkDir;
+
+use crate::cache::sha256;
+use crate::error::{Context, Result};
+
+/// A file and the device and inode it is stored at.
+type File = (PathBuf, (u64, u64));
+
+#[derive(Default)]
+pub struct Saved {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Display for Saved {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Deduplicated {} files, saving {}",
+            self.files,
+            HumanBytes(self.bytes)
+        )
+    }
+}
+
+/// Replaces byte-identical files across every installed version with links to a single copy.
+///
+/// Hardlinked files are unaffected when one of the versions sharing them is cleaned, since the
+/// data is only freed once its last link is removed.
+pub fn dedupe(install_location: &Path, reflink: bool) -> Result<Saved> {
+    let mut by_size: HashMap<(u64, u32), Vec<File>> = HashMap::new();
+    let versions = match read_dir(install_location) {
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Saved::default()),
+        x => x?,
+    };
+    for version in versions {
+        let version = version?;
+        // Hidden directories hold zman's own files, like the tarball cache and staged installs
+        if !version.file_type()?.is_dir() || version.file_name().to_string_lossy().starts_with('.')
+        {
+            continue;
+        }
+        for entry in WalkDir::new(version.path()) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.len() == 0 {
+                continue;
+            }
+            by_size
+                .entry((metadata.len(), metadata.mode()))
+                .or_default()
+                .push((entry.into_path(), (metadata.dev(), metadata.ino())));
+        }
+    }
+
+    let mut saved = Saved::default();
+    for ((size, _), files) in by_size {
+        if files.iter().all(|(_, inode)| *inode == files[0].1) {
+            continue;
+        }
+        // Files can only be linked to others on the same filesystem
+        let mut by_hash: HashMap<(String, u64), Vec<File>> = HashMap::new();
+        for (path, inode) in files {
+            by_hash
+                .entry((sha256(&path)?, inode.0))
+                .or_default()
+                .push((path, inode));
+        }
+        for files in by_hash.into_values() {
+            let (original, inode) = &files[0];
+            for (path, _) in files.iter().skip(1).filter(|(_, x)| x != inode) {
+                if replace(original, path, reflink)
+                    .with_context(|| format!("Cannot deduplicate {:?}", path))?
+                {
+                    saved.files += 1;
+                    saved.bytes += size;
+                }
+            }
+        }
+    }
+    Ok(saved)
+}
+
+/// Returns whether the replaced file's data was freed, which it is not when the file cannot be
+/// linked to the original because they are on different filesystems.
+fn replace(original: &Path, path: &Path, reflink: bool) -> Result<bool> {
+    let freed = path.metadata()?.nlink() == 1;
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".zman-dedupe");
+    let temp = PathBuf::from(temp);
+    match remove_file(&temp) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    };
+    if !reflink || reflink_copy::reflink(original, &temp).is_err() {
+        match hard_link(original, &temp) {
+            // Bind mounts refuse links across them even within one filesystem
+            Err(e) if e.raw_os_error() == Some(libc::EXDEV) => return Ok(false),
+            x => x?,
+        }
+    }
+    rename(&temp, path)?;
+    Ok(freed)
+}
+
+#[test]
+fn it_dedupe() {
+    use std::fs::{create_dir_all, write};
+    use temp_dir::TempDir;
+
+    let temp = TempDir::new().unwrap();
+    for version in ["0.11.0", "0.12.0"] {
+        create_dir_all(temp.child(version).join("lib")).unwrap();
+        write(
+            temp.child(version).join("lib/std.zig"),
+            "const std = @This();",
+        )
+        .unwrap();
+    }
+    create_dir_all(temp.child(".cache")).unwrap();
+    write(temp.child(".cache/std.zig"), "const std = @This();").unwrap();
+    let saved = dedupe(temp.path(), false).unwrap();
+    assert_eq!(saved.files, 1);
+    assert_eq!(temp.child(".cache/std.zig").metadata().unwrap().nlink(), 1);
+    assert_eq!(
+        temp.child("0.11.0/lib/std.zig").metadata().unwrap().ino(),
+        temp.child("0.12.0/lib/std.zig").metadata().unwrap().ino()
+    );
+    assert_eq!(dedupe(temp.path(), false).unwrap().files, 0);
+}
@@ -0,0 +1,480 @@
+use std::env::{split_paths, temp_dir, var_os};
+use std::fs::{read_dir, read_link, remove_dir_all, remove_file};
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::error::{bail, Error, Result};
+use crate::install::make_removable;
+use crate::installs::installs;
+use crate::state::{InstallRecord, State};
+use crate::Zman;
+
+/// Temporary directories are left alone for this long in case the zman that made them is still
+/// running, where it cannot be told from its process ID.
+const STALE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    /// Something that may surprise the user but does not break zman.
+    Warning,
+    /// Something broken.
+    Problem,
+}
+
+/// The outcome of one check.
+#[derive(Serialize)]
+pub struct Check {
+    /// What was checked, like `path` or `install 0.12.0`.
+    pub name: String,
+    pub status: Status,
+    pub message: String,
+    /// Whether [`Check::fix`] can repair it.
+    pub fixable: bool,
+    #[serde(skip)]
+    fix: Option<Fix>,
+}
+
+enum Fix {
+    RebuildState,
+    Relink(PathBuf),
+    LinkVersions,
+    Reinstall(String),
+    Record(String),
+    Forget(String),
+    Remove(PathBuf),
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: Status, message: impl Into<String>) -> Check {
+        Check {
+            name: name.into(),
+            status,
+            message: message.into(),
+            fixable: false,
+            fix: None,
+        }
+    }
+
+    fn with_fix(mut self, fix: Fix) -> Check {
+        self.fixable = true;
+        self.fix = Some(fix);
+        self
+    }
+
+    /// Repairs what was found, which may download a version again.
+    pub fn fix(&self, zman: &Zman) -> Result<()> {
+        let Some(fix) = &self.fix else {
+            bail!(Invalid, "{} cannot be fixed by zman", self.name)
+        };
+        match fix {
+            Fix::RebuildState => {
+                State::rebuild(&zman.install, &zman.link)?;
+            }
+            Fix::Relink(dir) => {
+                let state = zman.state()?;
+                let Some(default) = &state.default else {
+                    bail!(NotFound, "No default version to link")
+                };
+                let dropins = match state.links.iter().find(|x| x.dir == *dir) {
+                    Some(x) if !x.dropins => Vec::new(),
+                    _ => zman.config.dropins(),
+                };
+                let toolchain = zman.toolchain(&default.install, &state);
+                zman.set_default(&default.spec, &toolchain, dir, &dropins)?;
+            }
+            Fix::LinkVersions => zman.link_versions(&mut zman.state()?)?,
+            Fix::Reinstall(name) => {
+                zman.repair(name)?;
+            }
+            Fix::Record(name) => {
+                let mut state = zman.state()?;
+                let path = zman.install.join(name);
+                state.installs.insert(
+                    name.clone(),
+                    InstallRecord {
+                        version: zig_version(&path.join("zig")).unwrap_or_else(|_| name.clone()),
+                        installed: path.metadata()?.modified()?,
+                        shasum: None,
+                    },
+                );
+                state.save(&zman.install)?;
+            }
+            Fix::Forget(name) => {
+                let mut state = zman.state()?;
+                state.installs.remove(name);
+                state.save(&zman.install)?;
+            }
+            Fix::Remove(path) if path.is_dir() => {
+                make_removable(path)?;
+                remove_dir_all(path)?
+            }
+            Fix::Remove(path) => remove_file(path)?,
+        }
+        Ok(())
+    }
+}
+
+/// Checks the config file, the state file, link directories, `PATH`, every install and leftover temporary files.
+pub fn check(zman: &Zman) -> Result<Vec<Check>> {
+    let mut checks = Vec::new();
+    match Config::load(&zman.config_file) {
+        Err(e) => checks.push(Check::new("config", Status::Problem, describe(&e))),
+        Ok(x) if !x.unknown.is_empty() => checks.push(Check::new(
+            "config",
+            Status::Warning,
+            format!(
+                "Unknown keys {} in {:?} are ignored",
+                x.unknown.join(", "),
+                zman.config_file
+            ),
+        )),
+        Ok(_) => {}
+    }
+    let state = match zman.state() {
+        Ok(x) => {
+            checks.push(Check::new("state", Status::Ok, "State file is valid"));
+            x
+        }
+        Err(e) => {
+            checks.push(
+                Check::new("state", Status::Problem, describe(&e)).with_fix(Fix::RebuildState),
+            );
+            return Ok(checks);
+        }
+    };
+    check_links(zman, &state, &mut checks)?;
+    check_installs(zman, &state, &mut checks)?;
+    check_temp(zman, &mut checks)?;
+    Ok(checks)
+}
+
+fn check_links(zman: &Zman, state: &State, checks: &mut Vec<Check>) -> Result<()> {
+    let path: Vec<PathBuf> = var_os("PATH")
+        .map(|x| split_paths(&x).collect())
+        .unwrap_or_default();
+    let Some(default) = &state.default else {
+        checks.push(Check::new(
+            "default",
+            Status::Warning,
+            "No default version. Set one with `zman default`",
+        ));
+        return Ok(());
+    };
+    let dirs: Vec<&Path> = state.links.iter().map(|x| x.dir.as_path()).collect();
+    for link in &state.links {
+        let name = format!("path {}", link.dir.display());
+        let Some(i) = path.iter().position(|x| *x == link.dir) else {
+            checks.push(Check::new(
+                name,
+                Status::Warning,
+                format!("{:?} is not in PATH. Add it to PATH", link.dir),
+            ));
+            continue;
+        };
+        let shadow = path[..i]
+            .iter()
+            .map(|x| x.join("zig"))
+            .find(|x| is_executable(x) && !dirs.iter().any(|dir| x.parent() == Some(dir)));
+        match shadow {
+            Some(x) => checks.push(Check::new(
+                name,
+                Status::Warning,
+                format!(
+                    "{:?} comes first in PATH and is run instead of {:?}",
+                    x,
+                    link.dir.join("zig")
+                ),
+            )),
+            None => checks.push(Check::new(name, Status::Ok, "zig is found in PATH")),
+        }
+    }
+    for link in &state.links {
+        let name = format!("link {}", link.dir.display());
+        let relink = Fix::Relink(link.dir.clone());
+        let zig = link.dir.join("zig");
+        let expected = zman.install.join(&default.install).join("zig");
+        match read_link(&zig) {
+            Err(_) => {
+                checks.push(
+                    Check::new(name, Status::Problem, format!("{:?} is not linked", zig))
+                        .with_fix(relink),
+                );
+                continue;
+            }
+            Ok(x) if !x.exists() => {
+                checks.push(
+                    Check::new(
+                        name,
+                        Status::Problem,
+                        format!("{:?} points at {:?}, which does not exist", zig, x),
+                    )
+                    .with_fix(relink),
+                );
+                continue;
+            }
+            Ok(x) if x != expected => {
+                checks.push(
+                    Check::new(
+                        name,
+                        Status::Warning,
+                        format!(
+                            "{:?} points at {:?} instead of the default version {}",
+                            zig, x, default.install
+                        ),
+                    )
+                    .with_fix(relink),
+                );
+                continue;
+            }
+            Ok(_) => {}
+        }
+        let mut names: Vec<String> = state.targets.iter().flat_map(|x| x.names()).collect();
+        if link.dropins {
+            names.extend(
+                zman.config
+                    .dropins()
+                    .iter()
+                    .map(|x| crate::link::DROPIN_PREFIX.to_string() + x),
+            );
+        }
+        let broken: Vec<String> = names
+            .into_iter()
+            .filter(|x| !link.dir.join(x).exists())
+            .collect();
+        let versioned: Vec<&String> = state
+            .versioned
+            .iter()
+            .filter(|x| !link.dir.join(x).exists())
+            .collect();
+        if !broken.is_empty() {
+            checks.push(
+                Check::new(
+                    name,
+                    Status::Problem,
+                    format!("Drop-ins {} are missing or broken", broken.join(", ")),
+                )
+                .with_fix(relink),
+            );
+        } else if !versioned.is_empty() {
+            checks.push(
+                Check::new(
+                    name,
+                    Status::Problem,
+                    format!(
+                        "Versioned links {} are missing or broken",
+                        versioned
+                            .iter()
+                            .map(|x| x.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                )
+                .with_fix(Fix::LinkVersions),
+            );
+        } else {
+            checks.push(Check::new(
+                name,
+                Status::Ok,
+                format!("zig is linked to {}", default.install),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_installs(zman: &Zman, state: &State, checks: &mut Vec<Check>) -> Result<()> {
+    let installs = installs(&zman.install)?;
+    for install in &installs {
+        let name = format!("install {}", install.name);
+        let record = state.installs.get(&install.name);
+        let expected = match record {
+            Some(x) => x.version.clone(),
+            None => install.name.clone(),
+        };
+        let reinstall = Fix::Reinstall(install.name.clone());
+        let zig = install.path.join("zig");
+        if !is_executable(&zig) {
+            checks.push(
+                Check::new(
+                    name,
+                    Status::Problem,
+                    format!("{:?} is missing or not executable", zig),
+                )
+                .with_fix(reinstall),
+            );
+            continue;
+        }
+        if !install.path.join("lib").is_dir() {
+            checks.push(
+                Check::new(name, Status::Problem, "The standard library is missing")
+                    .with_fix(reinstall),
+            );
+            continue;
+        }
+        match zig_version(&zig) {
+            Err(e) => checks.push(
+                Check::new(name, Status::Problem, format!("zig version failed: {}", e))
+                    .with_fix(reinstall),
+            ),
+            Ok(x) if x != expected => checks.push(
+                Check::new(
+                    name,
+                    Status::Problem,
+                    format!("zig version printed {} instead of {}", x, expected),
+                )
+                .with_fix(reinstall),
+            ),
+            Ok(_) if record.is_none() => checks.push(
+                Check::new(name, Status::Warning, "Not recorded in the state file")
+                    .with_fix(Fix::Record(install.name.clone())),
+            ),
+            Ok(x) => checks.push(Check::new(
+                name,
+                Status::Ok,
+                format!("zig version is {}", x),
+            )),
+        }
+    }
+    for name in state.installs.keys() {
+        if !installs.iter().any(|x| x.name == *name) {
+            checks.push(
+                Check::new(
+                    format!("install {}", name),
+                    Status::Warning,
+                    "Recorded in the state file but not installed",
+                )
+                .with_fix(Fix::Forget(name.clone())),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Finds temporary directories left by interrupted installs and half written state files.
+fn check_temp(zman: &Zman, checks: &mut Vec<Check>) -> Result<()> {
+    let mut stale = Vec::new();
+    let state_tmp = zman.install.join("state.json.tmp");
+    if state_tmp.exists() {
+        stale.push(state_tmp);
+    }
+    // Named zman<pid>-<counter> with both in hex
+    find_stale(&temp_dir(), "zman", &mut stale)?;
+    // Copies of installs being swapped in, named .zman<pid>-<install>-new or -old
+    find_stale(&zman.install, ".zman", &mut stale)?;
+    if stale.is_empty() {
+        checks.push(Check::new(
+            "temp",
+            Status::Ok,
+            "No leftover temporary files",
+        ));
+    }
+    for path in stale {
+        checks.push(
+            Check::new(
+                "temp",
+                Status::Warning,
+                format!("{:?} was left by an interrupted zman", path),
+            )
+            .with_fix(Fix::Remove(path)),
+        );
+    }
+    Ok(())
+}
+
+/// Adds directories in `dir` named `<prefix><pid>-` and something, with the pid in hex, whose
+/// zman is no longer running.
+fn find_stale(dir: &Path, prefix: &str, stale: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = match read_dir(dir) {
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        x => x?,
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(pid) = name
+            .to_str()
+            .and_then(|x| x.strip_prefix(prefix))
+            .and_then(|x| x.split_once('-'))
+            .and_then(|(pid, _)| u32::from_str_radix(pid, 16).ok())
+        else {
+            continue;
+        };
+        if entry.file_type()?.is_dir() && !is_running(pid, &entry.path()) {
+            stale.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Whether the zman that made `path` may still be running.
+fn is_running(pid: u32, path: &Path) -> bool {
+    if Path::new("/proc/self").exists() {
+        return Path::new("/proc").join(pid.to_string()).exists();
+    }
+    path.metadata()
+        .and_then(|x| x.modified())
+        .map(|x| SystemTime::now().duration_since(x).unwrap_or_default() < STALE_AFTER)
+        .unwrap_or(true)
+}
+
+/// An error followed by its causes, since doctor reports it instead of failing with it.
+fn describe(e: &Error) -> String {
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(x) = source {
+        message = format!("{}: {}", message, x);
+        source = x.source();
+    }
+    message
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|x| x.is_file() && x.permissions().mode() & 0o111 != 0)
+}
+
+fn zig_version(zig: &Path) -> Result<String> {
+    let output = Command::new(zig).arg("version").output()?;
+    if !output.status.success() {
+        bail!(Invalid, "{:?} exited with {}", zig, output.status)
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[test]
+fn it_doctor() {
+    use std::fs::{create_dir_all, write};
+    use temp_dir::TempDir;
+
+    let temp = TempDir::new().unwrap();
+    let zman = Zman {
+        config: Default::default(),
+        config_file: temp.child("config.toml"),
+        install: temp.child("zman"),
+        cache: temp.child("cache"),
+        link: temp.child("bin"),
+        progress: Default::default(),
+        system: false,
+    };
+    create_dir_all(zman.install.join("0.12.0/lib")).unwrap();
+    write(zman.install.join("0.12.0/zig"), "").unwrap();
+    let checks = check(&zman).unwrap();
+    let install = checks.iter().find(|x| x.name == "install 0.12.0").unwrap();
+    assert_eq!(install.status, Status::Problem);
+    assert!(install.fixable);
+
+    write(zman.install.join("state.json"), "{").unwrap();
+    let checks = check(&zman).unwrap();
+    assert_eq!(checks.len(), 1);
+    assert_eq!(checks[0].status, Status::Problem);
+    checks[0].fix(&zman).unwrap();
+    assert_eq!(check(&zman).unwrap()[0].status, Status::Ok);
+}
@@ -1,34 +1,97 @@
 // https://github.com/DemwE/rgetd/tree/main/src/download.rs
 
-use eyre::{bail, Result};
-use indicatif::{ProgressBar, ProgressStyle};
-use reqwest::Client;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
 use reqwest::Url;
-use std::fs::File;
+use reqwest::{Client, StatusCode};
+use std::fs::{remove_file, File};
+use std::future::Future;
 use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::path::Path;
+use std::time::Duration;
 
-pub async fn download_file(client: &Client, url: &str, save_path: &PathBuf) -> Result<()> {
+use crate::error::{bail, Error, ErrorKind, Result};
+use crate::interrupt::interruptible;
+use crate::progress::{Event, Progress, Ticker};
+
+/// Connections that take longer than this to open fail, so an unreachable server cannot hang zman.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Requests that receive nothing for this long fail.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn client() -> Result<Client> {
+    Ok(Client::builder().connect_timeout(CONNECT_TIMEOUT).build()?)
+}
+
+/// Awaits part of a response, giving up when a signal is received or nothing arrives in time.
+pub async fn read<F: Future>(future: F) -> Result<F::Output> {
+    match tokio::time::timeout(READ_TIMEOUT, interruptible(future)).await {
+        Ok(x) => x,
+        Err(_) => bail!(
+            Network,
+            "Nothing received for {} seconds",
+            READ_TIMEOUT.as_secs()
+        ),
+    }
+}
+
+pub async fn download_file(
+    client: &Client,
+    url: &str,
+    save_path: &Path,
+    expected: Option<u64>,
+    progress: Progress,
+) -> Result<()> {
+    let url_text = url;
     // Parse URL
-    let url = Url::parse(url)?;
+    let url = Url::parse(url)
+        .map_err(|e| Error::new(ErrorKind::Invalid, format!("Invalid URL {:?}: {}", url, e)))?;
 
-    // Check if file already exists
-    let mut start = 0;
-    if save_path.exists() {
-        start = save_path.metadata()?.len();
+    // Resume what an interrupted download left
+    let mut start = match save_path.metadata() {
+        Ok(x) if expected.is_none_or(|expected| x.len() <= expected) => x.len(),
+        _ => 0,
+    };
+    if start > 0 && Some(start) == expected {
+        return Ok(());
     }
 
     // Make GET request
-    let mut response = client
-        .get(url.clone())
-        .header("Range", format!("bytes={}-", start))
-        .send()
-        .await?;
+    let mut request = client.get(url.clone());
+    if start > 0 {
+        request = request.header("Range", format!("bytes={}-", start));
+    }
+    let mut response = read(request.send()).await??;
 
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        remove_file(save_path)?;
+        bail!(
+            Network,
+            "Server cannot resume the download, it starts over next time"
+        )
+    }
     if response.status().is_success() {
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            // The whole file is sent when the server does not support ranges
+            start = 0;
+        }
         // Get total file size from response headers
-        let total_size = response.content_length().unwrap_or(0);
-        let pb = ProgressBar::new(total_size);
+        let total = response.content_length().map(|x| start + x);
+        if let (Some(total), Some(expected)) = (total, expected) {
+            if total != expected {
+                bail!(
+                    Checksum,
+                    "Server sent {} bytes but the download index lists {}",
+                    total,
+                    expected
+                )
+            }
+        }
+        let total = total.or(expected);
+        let pb = if progress == Progress::Bar {
+            ProgressBar::new(total.unwrap_or(0))
+        } else {
+            ProgressBar::hidden()
+        };
         pb.set_style(
             ProgressStyle::default_bar()
                 .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} | {binary_bytes_per_sec} | eta {eta}")
@@ -37,21 +100,55 @@ pub async fn download_file(client: &Client, url: &str, save_path: &PathBuf) -> R
         );
 
         // Open file for writing
-        let file = File::create(save_path)?;
+        let file = if start > 0 {
+            progress.message(&format!("Resuming download at {}", HumanBytes(start)));
+            File::options().append(true).open(save_path)?
+        } else {
+            File::create(save_path)?
+        };
+        // Dropped on errors and interruption, flushing what was received so it can be resumed
         let mut buffered_file = BufWriter::new(file);
 
         // Read response in chunks and write to file with progress update
-        let mut downloaded = 0;
-        while let Some(chunk) = response.chunk().await? {
+        let mut downloaded = start;
+        pb.set_position(downloaded);
+        let mut ticker = Ticker::new(progress);
+        let event = |bytes| Event::Download {
+            url: url_text,
+            bytes,
+            total,
+        };
+        while let Some(chunk) = read(response.chunk()).await?? {
             buffered_file.write_all(&chunk)?;
             downloaded += chunk.len() as u64;
+            if let Some(total) = total.filter(|x| downloaded > *x) {
+                bail!(
+                    Checksum,
+                    "Server sent more than the expected {} bytes",
+                    total
+                )
+            }
             pb.set_position(downloaded);
+            if ticker.tick(downloaded) {
+                progress.emit(&event(downloaded));
+            }
+        }
+        if ticker.finish(downloaded) {
+            progress.emit(&event(downloaded));
         }
 
         buffered_file.flush()?; // Flush the buffer to ensure all data is written to disk
+        if let Some(total) = total.filter(|x| downloaded != *x) {
+            bail!(
+                Network,
+                "Download ended after {} of {} bytes",
+                downloaded,
+                total
+            )
+        }
 
         Ok(())
     } else {
-        bail!("Server status: {}", response.status());
+        bail!(Network, "Server status: {}", response.status());
     }
 }
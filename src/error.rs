@@ -0,0 +1,184 @@
+use std::error::Error as StdError;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+use serde::Serialize;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// What went wrong, for callers that handle some failures differently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A version, target or install that does not exist.
+    NotFound,
+    /// ziglang.org or a mirror could not be reached or answered with an error.
+    Network,
+    /// A download did not match the checksum published for it.
+    Checksum,
+    /// The filesystem refused access.
+    Permission,
+    /// Any other filesystem error.
+    Io,
+    /// Malformed input, like an unparsable version, index, state or config file.
+    Invalid,
+    /// Stopped by Ctrl-C or another signal, after cleaning up.
+    Interrupted,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    message: Option<String>,
+    source: Option<Box<dyn StdError + Send + Sync>>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Error {
+            kind,
+            message: Some(message.into()),
+            source: None,
+        }
+    }
+
+    fn from_source(kind: ErrorKind, source: impl StdError + Send + Sync + 'static) -> Self {
+        Error {
+            kind,
+            message: None,
+            source: Some(Box::new(source)),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Describes what was being done when the error happened, keeping its kind.
+    pub fn context(self, message: impl Into<String>) -> Self {
+        Error {
+            kind: self.kind,
+            message: Some(message.into()),
+            source: Some(Box::new(self)),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (&self.message, &self.source) {
+            (Some(x), _) => f.write_str(x),
+            (None, Some(x)) => Display::fmt(x, f),
+            (None, None) => write!(f, "{:?} error", self.kind),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        // Without a message the source is displayed in its place, so skip to its own source
+        let source = self.source.as_deref()?;
+        match self.message {
+            Some(_) => Some(source),
+            None => source.source(),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        let kind = match e.kind() {
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => {
+                ErrorKind::Permission
+            }
+            _ => ErrorKind::Io,
+        };
+        Error::from_source(kind, e)
+    }
+}
+
+impl From<walkdir::Error> for Error {
+    fn from(e: walkdir::Error) -> Self {
+        match e.into_io_error() {
+            Some(e) => e.into(),
+            None => Error::new(ErrorKind::Io, "Filesystem loop found"),
+        }
+    }
+}
+
+impl From<fs_extra::error::Error> for Error {
+    fn from(e: fs_extra::error::Error) -> Self {
+        let kind = match e.kind {
+            fs_extra::error::ErrorKind::PermissionDenied => ErrorKind::Permission,
+            _ => ErrorKind::Io,
+        };
+        Error::from_source(kind, e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::from_source(ErrorKind::Network, e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::from_source(ErrorKind::Invalid, e)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Error::from_source(ErrorKind::Invalid, e)
+    }
+}
+
+impl From<toml::ser::Error> for Error {
+    fn from(e: toml::ser::Error) -> Self {
+        Error::from_source(ErrorKind::Invalid, e)
+    }
+}
+
+impl From<semver::Error> for Error {
+    fn from(e: semver::Error) -> Self {
+        Error::from_source(ErrorKind::Invalid, e)
+    }
+}
+
+/// Adds a description of what was being done to any error convertible into [`Error`].
+pub(crate) trait Context<T> {
+    fn context(self, message: impl Into<String>) -> Result<T>;
+
+    fn with_context<M: Into<String>>(self, message: impl FnOnce() -> M) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn context(self, message: impl Into<String>) -> Result<T> {
+        self.map_err(|e| e.into().context(message))
+    }
+
+    fn with_context<M: Into<String>>(self, message: impl FnOnce() -> M) -> Result<T> {
+        self.map_err(|e| e.into().context(message()))
+    }
+}
+
+macro_rules! bail {
+    ($kind:ident, $($arg:tt)*) => {
+        return Err($crate::Error::new($crate::ErrorKind::$kind, format!($($arg)*)))
+    };
+}
+
+pub(crate) use bail;
+
+#[test]
+fn it_error_chain() {
+    let e: Result<()> =
+        Err(io::Error::from(io::ErrorKind::PermissionDenied)).context("Cannot write state file");
+    let e = e.unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Permission);
+    assert_eq!(e.to_string(), "Cannot write state file");
+    assert_eq!(e.source().unwrap().to_string(), "permission denied");
+    assert!(e.source().unwrap().source().is_none());
+}
@@ -0,0 +1,187 @@
+use std::collections::HashSet;
+use std::env::var_os;
+use std::fs::{read_dir, remove_dir_all, remove_file, File, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::error::{bail, Error, ErrorKind, Result};
+use crate::installs::disk_usage;
+use directories::BaseDirs;
+
+/// Zig's global cache directory, resolved the same way the compiler does.
+pub fn dir() -> Option<PathBuf> {
+    if let Some(x) = var_os("ZIG_GLOBAL_CACHE_DIR") {
+        return Some(PathBuf::from(x));
+    }
+    if let Some(x) = var_os("XDG_CACHE_HOME") {
+        return Some(PathBuf::from(x).join("zig"));
+    }
+    Some(BaseDirs::new()?.home_dir().join(".cache").join("zig"))
+}
+
+pub struct Entry {
+    pub path: PathBuf,
+    /// Latest of the access and modification times of a file, or the modification time of a
+    /// directory since listing it updates its access time.
+    pub used: SystemTime,
+    pub size: u64,
+}
+
+/// Every artifact, manifest, package and temporary entry in the cache, least recently used first.
+pub fn entries(dir: &Path) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for sub in ["o", "h", "z", "tmp"] {
+        let list = match read_dir(dir.join(sub)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            x => x?,
+        };
+        for entry in list {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let used = if metadata.is_dir() {
+                metadata.modified()?
+            } else {
+                metadata.modified()?.max(metadata.accessed()?)
+            };
+            entries.push(Entry {
+                size: disk_usage(&entry.path(), &mut HashSet::new())?,
+                path: entry.path(),
+                used,
+            });
+        }
+    }
+    entries.sort_by_key(|x| x.used);
+    Ok(entries)
+}
+
+/// Entries unused for longer than `older_than`, plus the least recently used ones still needed to
+/// bring the cache under `max_size`.
+pub fn select(
+    entries: Vec<Entry>,
+    older_than: Option<Duration>,
+    max_size: Option<u64>,
+) -> Vec<Entry> {
+    let now = SystemTime::now();
+    let mut total: u64 = entries.iter().map(|x| x.size).sum();
+    let mut selected = Vec::new();
+    for entry in entries {
+        let old = older_than
+            .is_some_and(|x| now.duration_since(entry.used).unwrap_or(Duration::ZERO) > x);
+        let over = max_size.is_some_and(|x| total > x);
+        if old || over {
+            total -= entry.size;
+            selected.push(entry);
+        }
+    }
+    selected
+}
+
+/// Removes `entries`, locking the manifest of each one while it goes so that no `zig` starts
+/// using it meanwhile. Returns the entries skipped because a running `zig` holds their manifest.
+pub fn remove(dir: &Path, entries: Vec<Entry>) -> Result<Vec<Entry>> {
+    let mut skipped = Vec::new();
+    for entry in entries {
+        // Dropped once the entry is gone, so only one manifest is open at a time
+        let _lock = match File::open(manifest(dir, &entry.path)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            x => {
+                let file = x?;
+                match file.try_lock() {
+                    Ok(()) => Some(file),
+                    Err(TryLockError::WouldBlock) => {
+                        skipped.push(entry);
+                        continue;
+                    }
+                    Err(TryLockError::Error(e)) => return Err(e.into()),
+                }
+            }
+        };
+        match remove_dir_all(&entry.path) {
+            Err(e) if e.kind() == io::ErrorKind::NotADirectory => remove_file(&entry.path)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            x => x?,
+        }
+    }
+    Ok(skipped)
+}
+
+/// The manifest in `h` that an entry belongs to, which is the entry itself for manifests.
+fn manifest(dir: &Path, entry: &Path) -> PathBuf {
+    if entry.parent().and_then(Path::file_name) == Some("h".as_ref()) {
+        return entry.to_owned();
+    }
+    let mut name = entry.file_name().unwrap_or_default().to_owned();
+    name.push(".txt");
+    dir.join("h").join(name)
+}
+
+/// Parses sizes like `512MiB`, `5G` or `1024`.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|x: char| !x.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| Error::new(ErrorKind::Invalid, format!("Invalid size {:?}", size)))?;
+    let unit = match unit.trim().to_ascii_lowercase().trim_end_matches('b') {
+        "" => 1,
+        "k" | "ki" => 1 << 10,
+        "m" | "mi" => 1 << 20,
+        "g" | "gi" => 1 << 30,
+        "t" | "ti" => 1 << 40,
+        _ => bail!(Invalid, "Invalid size unit in {:?}", size),
+    };
+    number
+        .checked_mul(unit)
+        .ok_or_else(|| Error::new(ErrorKind::Invalid, format!("Size {:?} is too large", size)))
+}
+
+#[test]
+fn it_prune_select() {
+    let now = SystemTime::now();
+    let day = Duration::from_secs(86400);
+    let entry = |name: &str, days: u32, size| Entry {
+        path: PathBuf::from(name),
+        used: now - day * days,
+        size,
+    };
+    let entries = vec![
+        entry("o/old", 40, 10),
+        entry("o/big", 5, 100),
+        entry("h/new", 0, 10),
+    ];
+    let selected = select(entries, Some(day * 30), Some(50));
+    let names: Vec<_> = selected.iter().map(|x| x.path.to_str().unwrap()).collect();
+    assert_eq!(names, ["o/old", "o/big"]);
+    assert_eq!(parse_size("5GiB").unwrap(), 5 << 30);
+    assert!(parse_size("99999999999T").is_err());
+}
+
+#[test]
+fn it_prune_remove() {
+    use std::fs::{create_dir_all, write};
+    use temp_dir::TempDir;
+
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path();
+    for name in ["a", "b"] {
+        create_dir_all(dir.join("o").join(name)).unwrap();
+        create_dir_all(dir.join("h")).unwrap();
+        write(dir.join("h").join(format!("{}.txt", name)), "").unwrap();
+    }
+    let held = File::open(dir.join("h/b.txt")).unwrap();
+    held.lock().unwrap();
+    let entries = ["o/a", "o/b", "h/a.txt"].map(|x| Entry {
+        path: dir.join(x),
+        used: SystemTime::now(),
+        size: 0,
+    });
+    let skipped = remove(dir, entries.into()).unwrap();
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].path, dir.join("o/b"));
+    assert!(!dir.join("o/a").exists() && !dir.join("h/a.txt").exists());
+    assert!(dir.join("o/b").exists());
+}
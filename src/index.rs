@@ -0,0 +1,310 @@
+use std::env::consts::{ARCH, OS};
+use std::fs::{create_dir_all, read_to_string, write};
+use std::io;
+use std::path::Path;
+
+use reqwest::Client;
+use semver::{Version, VersionReq};
+use serde_json::Value;
+
+use crate::download::read;
+use crate::error::{Context, Error, ErrorKind, Result};
+use crate::progress::Progress;
+
+/// A Zig build for this machine listed in ziglang.org's download index.
+pub struct Release {
+    /// Exact version, like `0.12.0` or `0.13.0-dev.351+64ef45eb0` for master.
+    pub version: String,
+    pub url: String,
+    pub shasum: String,
+    /// Size of the tarball in bytes, when the index gives it.
+    pub size: Option<u64>,
+}
+
+impl Release {
+    /// Name of the directory the release is installed to, which stays `master` for master builds
+    /// so that each one replaces the last.
+    pub fn install_name(&self, spec: &str) -> String {
+        if spec == "master" {
+            "master".to_string()
+        } else {
+            self.version.clone()
+        }
+    }
+}
+
+/// A version listed in the download index with a build for this machine.
+pub struct Available {
+    /// `master` or the version itself.
+    pub name: String,
+    pub version: String,
+    pub date: Option<String>,
+}
+
+/// Every version in the index with a build for this machine, releases sorted oldest first
+/// followed by master.
+pub fn available(api: &Value) -> Result<Vec<Available>> {
+    let api_map = api
+        .as_object()
+        .ok_or_else(|| Error::new(ErrorKind::Invalid, "API could not be parsed"))?;
+    let tarball = format!("/{}-{}/tarball", ARCH, OS);
+    let mut available: Vec<Available> = api_map
+        .iter()
+        .filter(|(_, val)| val.pointer(&tarball).is_some())
+        .map(|(name, val)| Available {
+            name: name.clone(),
+            version: match val.pointer("/version").and_then(Value::as_str) {
+                Some(x) if name == "master" => x.to_string(),
+                _ => name.clone(),
+            },
+            date: val
+                .pointer("/date")
+                .and_then(Value::as_str)
+                .map(String::from),
+        })
+        .collect();
+    available.sort_by_key(|x| (x.name == "master", Version::parse(&x.name).ok()));
+    Ok(available)
+}
+
+/// Fetches the download index, falling back to the copy cached by the last successful fetch.
+pub async fn fetch_ziglang_api(
+    client: &Client,
+    cache_location: &Path,
+    progress: Progress,
+) -> Result<Value> {
+    let response = async {
+        let response = read(client.get("https://ziglang.org/download/index.json").send()).await??;
+        Ok::<_, Error>(read(response.error_for_status()?.text()).await??)
+    };
+    match response.await {
+        Ok(text) => {
+            let api = serde_json::from_str(&text).context("API could not be parsed")?;
+            create_dir_all(cache_location)?;
+            write(cache_location.join("index.json"), text)?;
+            Ok(api)
+        }
+        Err(e) if e.kind() == ErrorKind::Interrupted => Err(e),
+        Err(e) => {
+            let api = cached(cache_location)?
+                .ok_or(e)
+                .context("Cannot connect to ziglang.org API")?;
+            progress.message("Cannot connect to ziglang.org API, using cached index");
+            Ok(api)
+        }
+    }
+}
+
+/// The download index cached by the last successful fetch, if there is one.
+pub fn cached(cache_location: &Path) -> Result<Option<Value>> {
+    match read_to_string(cache_location.join("index.json")) {
+        Ok(text) => Ok(Some(
+            serde_json::from_str(&text).context("Cached API could not be parsed")?,
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Parses a version spec other than `latest` and `master`. A bare version like `0.12` matches
+/// every version it is a prefix of, while ranges like `>=0.11, <0.13` are given with operators.
+pub fn version_req(spec: &str) -> Result<VersionReq> {
+    let req = if spec.starts_with(|x: char| x.is_ascii_digit()) {
+        VersionReq::parse(&format!("={}", spec))
+    } else {
+        VersionReq::parse(spec)
+    };
+    req.with_context(|| format!("Invalid version {:?}", spec))
+}
+
+/// Finds the release matching `version`, which is a version or range, `latest` or `master`.
+pub fn parse_ziglang_api(api: &Value, version: &str) -> Result<Release> {
+    let _e = || Error::new(ErrorKind::Invalid, "API could not be parsed");
+
+    let tarball = format!("/{}-{}/tarball", ARCH, OS);
+    let sha = format!("/{}-{}/shasum", ARCH, OS);
+    let size = format!("/{}-{}/size", ARCH, OS);
+    let _e_arch = || {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("Zig binary for {} target not available", tarball),
+        )
+    };
+
+    match version {
+        "master" => {
+            let specific_version = api
+                .pointer("/master/version")
+                .ok_or_else(_e)?
+                .as_str()
+                .ok_or_else(_e)?
+                .to_string();
+            let url = api
+                .pointer(&format!("/master{}", tarball))
+                .ok_or_else(_e_arch)?
+                .as_str()
+                .ok_or_else(_e)?
+                .to_string();
+            let shasum = api
+                .pointer(&format!("/master{}", sha))
+                .ok_or_else(_e_arch)?
+                .as_str()
+                .ok_or_else(_e)?
+                .to_string();
+            Ok(Release {
+                version: specific_version,
+                url,
+                shasum,
+                size: tarball_size(api.pointer(&format!("/master{}", size))),
+            })
+        }
+        "latest" => {
+            let api_map = api.as_object().ok_or_else(_e)?;
+            let mut latest: Option<&Value> = None;
+            let mut latest_date: Option<&str> = None;
+            let mut latest_version: Option<&str> = None;
+            for (ver, val) in api_map {
+                if ver != "master" {
+                    match latest_date {
+                        None => {
+                            let date = val
+                                .pointer("/date")
+                                .ok_or_else(_e)?
+                                .as_str()
+                                .ok_or_else(_e)?;
+                            latest = Some(val);
+                            latest_date = Some(date);
+                            latest_version = Some(ver);
+                        }
+                        Some(x) => {
+                            let val_date = val
+                                .pointer("/date")
+                                .ok_or_else(_e)?
+                                .as_str()
+                                .ok_or_else(_e)?;
+                            if x < val_date {
+                                latest = Some(val);
+                                latest_date = Some(val_date);
+                                latest_version = Some(ver);
+                            }
+                        }
+                    }
+                }
+            }
+            let specific_version = latest_version
+                .ok_or_else(|| {
+                    Error::new(ErrorKind::NotFound, "Latest version could not be found")
+                })?
+                .to_string();
+            let url = latest
+                .ok_or_else(_e)?
+                .pointer(&tarball)
+                .ok_or_else(_e_arch)?
+                .as_str()
+                .ok_or_else(_e)?
+                .to_string();
+            let shasum = latest
+                .ok_or_else(_e)?
+                .pointer(&sha)
+                .ok_or_else(_e_arch)?
+                .as_str()
+                .ok_or_else(_e)?
+                .to_string();
+            Ok(Release {
+                version: specific_version,
+                url,
+                shasum,
+                size: tarball_size(latest.and_then(|x| x.pointer(&size))),
+            })
+        }
+        version => {
+            let api_map = api.as_object().ok_or_else(_e)?;
+            let version_required = version_req(version)?;
+            let latest_matching_version = api_map
+                .keys()
+                .filter_map(|x| Version::parse(x).ok())
+                .filter(|x| version_required.matches(x))
+                .max();
+            let specific_version = latest_matching_version
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::NotFound,
+                        format!("Version {} could not be found", version),
+                    )
+                })?
+                .to_string();
+            let url = api
+                .pointer(&format!("/{}{}", specific_version, tarball))
+                .ok_or_else(_e_arch)?
+                .as_str()
+                .ok_or_else(_e)?
+                .to_string();
+            let shasum = api
+                .pointer(&format!("/{}{}", specific_version, sha))
+                .ok_or_else(_e_arch)?
+                .as_str()
+                .ok_or_else(_e)?
+                .to_string();
+            Ok(Release {
+                size: tarball_size(api.pointer(&format!("/{}{}", specific_version, size))),
+                version: specific_version,
+                url,
+                shasum,
+            })
+        }
+    }
+}
+
+/// The index gives sizes as strings, like `"47082308"`.
+fn tarball_size(value: Option<&Value>) -> Option<u64> {
+    match value? {
+        Value::String(x) => x.parse().ok(),
+        x => x.as_u64(),
+    }
+}
+
+#[test]
+fn it_available() {
+    let build = serde_json::json!({ "tarball": "https://ziglang.org/zig.tar.xz", "shasum": "00" });
+    let target = format!("{}-{}", ARCH, OS);
+    let api = serde_json::json!({
+        "master": { "version": "0.14.0-dev.1+abc", "date": "2024-06-01", target.clone(): build },
+        "0.12.0": { "date": "2024-04-20", target.clone(): build },
+        "0.9.1": { "date": "2022-02-14", target: build },
+        "0.1.1": { "date": "2017-10-17" },
+    });
+    let available = available(&api).unwrap();
+    let names: Vec<_> = available.iter().map(|x| x.name.as_str()).collect();
+    assert_eq!(names, ["0.9.1", "0.12.0", "master"]);
+    assert_eq!(available[2].version, "0.14.0-dev.1+abc");
+}
+
+#[test]
+fn it_version_req() {
+    let matches = |spec, version| {
+        version_req(spec)
+            .unwrap()
+            .matches(&Version::parse(version).unwrap())
+    };
+    assert!(matches("0.12", "0.12.1"));
+    assert!(!matches("0.12", "0.13.0"));
+    assert!(matches("0.12.0", "0.12.0"));
+    assert!(!matches("0.12.0", "0.12.1"));
+    assert!(matches(">=0.11, <0.13", "0.11.0"));
+    assert!(version_req("twelve").is_err());
+}
+
+#[test]
+fn it_parse_ziglang_api() {
+    // let x = parse_ziglang_api("0.10").unwrap();
+    // dbg!(x);
+    let api = serde_json::json!({
+        "0.12.0": { "date": "2024-04-20", format!("{}-{}", ARCH, OS): {
+            "tarball": "https://ziglang.org/zig.tar.xz", "shasum": "00", "size": "47082308"
+        } },
+    });
+    assert_eq!(
+        parse_ziglang_api(&api, "0.12").unwrap().size,
+        Some(47082308)
+    );
+}
@@ -0,0 +1,535 @@
+use std::env::consts::{ARCH, OS};
+use std::ffi::{OsStr, OsString};
+use std::fs::{create_dir_all, remove_dir_all, rename, set_permissions, File, Permissions};
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+
+use console::Term;
+use fs_extra::dir::{copy, CopyOptions};
+use indicatif::HumanBytes;
+use reqwest::Client;
+use tar::{Archive, EntryType};
+use walkdir::WalkDir;
+use xz2::read::XzDecoder;
+
+use crate::cache::{self, Cache};
+use crate::download::download_file;
+use crate::error::{bail, Context, Error, Result};
+use crate::index::Release;
+use crate::interrupt;
+use crate::link::remove_if_exists;
+use crate::progress::{Event, Progress, Ticker};
+
+pub fn check_sha256(file: &Path, hash: &str) -> Result<()> {
+    let x = cache::sha256(file)?;
+    if hash != x {
+        bail!(Checksum, "Expected SHA-256 {} but got {}", hash, x)
+    }
+    Ok(())
+}
+
+/// Returns a verified tarball of the release from the cache, or downloads it from each of the
+/// mirrors in turn and finally ziglang.org.
+pub async fn fetch_tarball(
+    client: &Client,
+    cache: &Cache,
+    mirrors: &[String],
+    release: &Release,
+    progress: Progress,
+) -> Result<PathBuf> {
+    if let Some(x) = cache.get(&release.shasum)? {
+        progress.message(&format!("Using cached Zig version {}", release.version));
+        return Ok(x);
+    }
+    let file_name = release.url.rsplit('/').next().unwrap_or_default();
+    let mut urls: Vec<String> = mirrors
+        .iter()
+        .map(|x| format!("{}/{}", x.trim_end_matches('/'), file_name))
+        .collect();
+    urls.push(release.url.clone());
+    let mut error = None;
+    for url in &urls {
+        let part = cache.part(&release.shasum)?;
+        let downloaded = download_file(client, url, &part, release.size, progress)
+            .await
+            .with_context(|| format!("Downloading {:?} from {} failed", release.version, url))
+            .and_then(|_| {
+                progress.emit(&Event::Verify {
+                    version: &release.version,
+                });
+                check_sha256(&part, &release.shasum)
+                    .with_context(|| format!("Checksum failed for {:?}", release.version))
+            });
+        match downloaded {
+            Ok(()) => return cache.commit(&release.shasum),
+            // What was downloaded is kept to be resumed next time
+            Err(e) if e.kind() == crate::ErrorKind::Interrupted => return Err(e),
+            Err(e) => {
+                if e.kind() == crate::ErrorKind::Checksum {
+                    remove_if_exists(&part)?;
+                }
+                if urls.len() > 1 {
+                    progress.message(&format!("{}, trying the next mirror", e));
+                }
+                error = Some(e);
+            }
+        }
+    }
+    Err(error
+        .unwrap_or_else(|| Error::new(crate::ErrorKind::NotFound, "No mirror to download from")))
+}
+
+/// Tarballs unpacking to more than this are rejected, as Zig releases take around 300 MiB.
+const MAX_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+/// Tarballs with more entries than this are rejected, as Zig releases have around 20000.
+const MAX_ENTRIES: u64 = 200_000;
+
+/// Names the top-level directory of the tarball for `version` may have on this machine, which
+/// changed from `zig-linux-x86_64-<version>` to `zig-x86_64-linux-<version>` in 0.14.1.
+fn top_level_names(version: &str) -> [String; 2] {
+    [
+        format!("zig-{}-{}-{}", OS, ARCH, version),
+        format!("zig-{}-{}-{}", ARCH, OS, version),
+    ]
+}
+
+/// The top-level directory of an entry path, which must be relative and not contain `..`.
+fn top_level(path: &Path) -> Option<&OsStr> {
+    let mut top = None;
+    for x in path.components() {
+        match x {
+            Component::Normal(x) => {
+                top.get_or_insert(x);
+            }
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    top
+}
+
+/// Whether a path relative to the root of the archive stays inside the directory `top` once `..`
+/// are resolved.
+fn stays_inside(path: &Path, top: &OsStr) -> bool {
+    let mut parts = Vec::new();
+    for x in path.components() {
+        match x {
+            Component::Normal(x) => parts.push(x),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if parts.pop().is_none() {
+                    return false;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    parts.first() == Some(&top)
+}
+
+/// Extracts the tarball of Zig `version` and installs the directory it holds to
+/// `install_location`. Tarballs with paths or links leading outside that directory, other entries
+/// besides it, device files or an unreasonable size are rejected before anything is installed.
+pub fn extract_and_copy(
+    file: &Path,
+    extract_location: PathBuf,
+    install_location: &Path,
+    version: &str,
+    progress: Progress,
+) -> Result<()> {
+    let _e = || format!("Extracting {:?} failed", file);
+    let xz = XzDecoder::new(File::open(file).with_context(_e)?);
+    let mut tar = Archive::new(xz);
+    // Permission bits are kept but setuid, setgid and sticky bits are dropped
+    tar.set_preserve_permissions(false);
+    tar.set_unpack_xattrs(false);
+    let t = Term::stderr();
+    let bar = progress == Progress::Bar;
+    if bar {
+        t.write_line("Extracting Zig...")?;
+    }
+    let names = top_level_names(version);
+    let mut top: Option<OsString> = None;
+    let mut files = 0;
+    let mut size = 0;
+    let mut ticker = Ticker::new(progress);
+    create_dir_all(&extract_location).with_context(_e)?;
+    for entry in tar.entries().with_context(_e)? {
+        let mut entry = entry.with_context(_e)?;
+        let kind = entry.header().entry_type();
+        if kind == EntryType::XGlobalHeader {
+            continue;
+        }
+        files += 1;
+        size += entry.header().size().with_context(_e)?;
+        if files > MAX_ENTRIES || size > MAX_SIZE {
+            bail!(
+                Invalid,
+                "{:?} has more than {} entries or unpacks to more than {}",
+                file,
+                MAX_ENTRIES,
+                HumanBytes(MAX_SIZE)
+            )
+        }
+        let path = entry.path().with_context(_e)?.into_owned();
+        let Some(entry_top) = top_level(&path) else {
+            bail!(Invalid, "{:?} has an entry outside of it, {:?}", file, path)
+        };
+        match &top {
+            None if names.iter().any(|x| entry_top == x.as_str()) => top = Some(entry_top.into()),
+            None => bail!(
+                Invalid,
+                "{:?} holds {:?} instead of {}",
+                file,
+                entry_top,
+                names[0]
+            ),
+            Some(x) if x != entry_top => bail!(
+                Invalid,
+                "{:?} has more than one top-level entry, {:?} and {:?}",
+                file,
+                x,
+                entry_top
+            ),
+            Some(_) => {}
+        }
+        let top = top.as_deref().unwrap_or_default();
+        let inside = match kind {
+            EntryType::Regular | EntryType::Continuous | EntryType::Directory => true,
+            EntryType::Symlink | EntryType::Link => {
+                let target = entry.link_name().with_context(_e)?.unwrap_or_default();
+                // Symbolic links are relative to their own directory, hard links to the archive
+                let target = match kind {
+                    EntryType::Symlink => path.parent().unwrap_or(&path).join(target),
+                    _ => target.into_owned(),
+                };
+                stays_inside(&target, top)
+            }
+            _ => bail!(Invalid, "{:?} has a special file {:?}", file, path),
+        };
+        if !inside {
+            bail!(
+                Invalid,
+                "{:?} has a link leading outside of it, {:?}",
+                file,
+                path
+            )
+        }
+        if !entry.unpack_in(&extract_location).with_context(_e)? {
+            bail!(Invalid, "{:?} has an entry outside of it, {:?}", file, path)
+        }
+        interrupt::check()?;
+        if ticker.tick(files) {
+            progress.emit(&Event::Extract { files });
+        }
+    }
+    if ticker.finish(files) {
+        progress.emit(&Event::Extract { files });
+    }
+    let Some(top) = top else {
+        bail!(Invalid, "{:?} is empty", file)
+    };
+    if bar {
+        t.clear_line()?;
+        t.write_line("Installing Zig...")?;
+    }
+    // Copied next to the install first, so an interrupted copy never leaves a partial install
+    let staging = Staging::new(install_location, "new");
+    let mut opts = CopyOptions::new();
+    opts.content_only = true;
+    create_dir_all(&staging.0)?;
+    copy(extract_location.join(top), &staging.0, &opts)?;
+    interrupt::check()?;
+    // Files may be hardlinked into other versions, so they must be replaced rather than overwritten
+    // The old install is only removed once the new one is in place
+    let old = Staging::new(install_location, "old");
+    let moved = match rename(install_location, &old.0) {
+        Err(e) if e.kind() == ErrorKind::NotFound => false,
+        x => x.map(|_| true)?,
+    };
+    if let Err(e) = rename(&staging.0, install_location) {
+        if moved && rename(&old.0, install_location).is_err() {
+            // Kept for zman doctor to find rather than losing the only copy
+            std::mem::forget(old);
+        }
+        return Err(e.into());
+    }
+    drop(old);
+    if bar {
+        t.clear_line()?;
+    }
+    Ok(())
+}
+
+/// A directory next to an install, named like the temporary directories of zman so leftovers of
+/// a killed zman are found by `zman doctor`, and removed when dropped unless moved away.
+struct Staging(PathBuf);
+
+impl Staging {
+    fn new(install_location: &Path, suffix: &str) -> Staging {
+        let name = install_location.file_name().unwrap_or_default();
+        Staging(install_location.with_file_name(format!(
+            ".zman{:x}-{}-{}",
+            std::process::id(),
+            name.to_string_lossy(),
+            suffix
+        )))
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if make_removable(&self.0).is_ok() {
+            let _ = remove_dir_all(&self.0);
+        }
+    }
+}
+
+/// Gives the group of every file below `root` the permissions its owner has, and makes new
+/// directories inherit their group, for installs shared between accounts.
+pub fn share_with_group(root: &Path) -> Result<()> {
+    for entry in WalkDir::new(root) {
+        let entry = entry?;
+        if entry.file_type().is_symlink() {
+            continue;
+        }
+        let mode = entry.metadata()?.permissions().mode();
+        let mut shared = mode | (mode & 0o700) >> 3;
+        if entry.file_type().is_dir() {
+            shared |= 0o2000;
+        }
+        if shared != mode {
+            set_permissions(entry.path(), Permissions::from_mode(shared))?;
+        }
+    }
+    Ok(())
+}
+
+/// Takes write permission away from everything below `root` so it cannot be edited or replaced
+/// by accident, or gives it back to the owner, and the group when `shared`.
+pub fn set_read_only(root: &Path, read_only: bool, shared: bool) -> Result<()> {
+    let write = if shared { 0o220 } else { 0o200 };
+    for entry in WalkDir::new(root) {
+        let entry = entry?;
+        if entry.file_type().is_symlink() {
+            continue;
+        }
+        let mode = entry.metadata()?.permissions().mode();
+        let changed = match read_only {
+            true => mode & !0o222,
+            false => mode | write,
+        };
+        if changed != mode {
+            set_permissions(entry.path(), Permissions::from_mode(changed))?;
+        }
+    }
+    Ok(())
+}
+
+/// Gives write permission back on every directory below `root` that lacks it, so what they hold
+/// can be removed: to the owner, and to the group for directories of a shared install, which
+/// have the setgid bit. Files are left alone, deleting them only needs a writable directory.
+pub fn make_removable(root: &Path) -> Result<()> {
+    if !root.try_exists()? {
+        return Ok(());
+    }
+    for entry in WalkDir::new(root) {
+        let entry = entry?;
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+        let mode = entry.metadata()?.permissions().mode();
+        let write = if mode & 0o2000 != 0 { 0o220 } else { 0o200 };
+        if mode | write != mode {
+            set_permissions(entry.path(), Permissions::from_mode(mode | write))?;
+        }
+    }
+    Ok(())
+}
+
+/// Makes `dir` a Zig lib directory for `--zig-lib-dir` whose `std` is a writable copy of the one
+/// in `lib`, while everything else links to the original.
+pub fn overlay_std(lib: &Path, dir: &Path) -> Result<()> {
+    if dir.try_exists()? {
+        bail!(
+            Invalid,
+            "{:?} already exists. Remove it to start over from the installed std",
+            dir
+        )
+    }
+    create_dir_all(dir)?;
+    for entry in std::fs::read_dir(lib)? {
+        let entry = entry?;
+        if entry.file_name() == "std" {
+            let mut opts = CopyOptions::new();
+            opts.copy_inside = true;
+            copy(entry.path(), dir, &opts)?;
+            set_read_only(&dir.join("std"), false, false)?;
+        } else {
+            std::os::unix::fs::symlink(entry.path(), dir.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn it_download() {
+    // let x = download_tarxz("https://ziglang.org/download/0.11.0/zig-linux-x86_64-0.11.0.tar.xz");
+    // dbg!(r);
+}
+
+#[test]
+fn it_sha256() {
+    check_sha256(
+        &PathBuf::from("/tmp/zig-linux-x86_64-0.11.0.tar.xz"),
+        "2d00e789fec4f71790a6e7bf83ff91d564943c5ee843c5fd966efc474b423047",
+    )
+    .unwrap();
+}
+
+#[test]
+fn it_extract() {
+    // extract_tarxz(
+    //     &PathBuf::from("/tmp/zig-linux-x86_64-0.11.0.tar.xz"),
+    //     &PathBuf::from("/tmp/zig")
+    // )
+    // .unwrap();
+}
+
+#[test]
+fn it_extract_unsafe() {
+    use std::os::unix::fs::PermissionsExt;
+    use tar::{Builder, Header};
+    use temp_dir::TempDir;
+    use xz2::write::XzEncoder;
+
+    let temp = TempDir::new().unwrap();
+    let top = &top_level_names("0.13.0")[0];
+    // Entries are written with raw names since the builder refuses unsafe ones
+    let tarball = |name: &str, entries: &[(&str, EntryType, &str, u32)]| {
+        let path = temp.child(name);
+        let mut builder = Builder::new(XzEncoder::new(File::create(&path).unwrap(), 1));
+        for (name, kind, link, mode) in entries {
+            let mut header = Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.as_old_mut().linkname[..link.len()].copy_from_slice(link.as_bytes());
+            header.set_entry_type(*kind);
+            header.set_mode(*mode);
+            header.set_size(0);
+            header.set_cksum();
+            builder.append(&header, &[][..]).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        path
+    };
+    let extract = |file: &Path| {
+        let install = temp.child("install");
+        let _ = remove_dir_all(&install);
+        let _ = remove_dir_all(temp.child("extract"));
+        extract_and_copy(
+            file,
+            temp.child("extract"),
+            &install,
+            "0.13.0",
+            Progress::Hidden,
+        )
+    };
+
+    let zig = format!("{}/zig", top);
+    let good = tarball(
+        "good.tar.xz",
+        &[
+            (top, EntryType::Directory, "", 0o755),
+            (&zig, EntryType::Regular, "", 0o4755),
+            (&format!("{}/lib", top), EntryType::Symlink, "zig", 0o777),
+        ],
+    );
+    extract(&good).unwrap();
+    let mode = temp
+        .child("install/zig")
+        .metadata()
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o7777, 0o755);
+
+    for (name, entries) in [
+        (
+            "absolute",
+            vec![("/etc/zig", EntryType::Regular, "", 0o644)],
+        ),
+        ("parent", vec![("../zig", EntryType::Regular, "", 0o644)]),
+        (
+            "inner-parent",
+            vec![
+                (zig.as_str(), EntryType::Regular, "", 0o644),
+                ("x/../../zig", EntryType::Regular, "", 0o644),
+            ],
+        ),
+        (
+            "other-name",
+            vec![("zig-linux-x86_64-0.12.0/zig", EntryType::Regular, "", 0o644)],
+        ),
+        (
+            "two-tops",
+            vec![
+                (zig.as_str(), EntryType::Regular, "", 0o644),
+                ("extra/zig", EntryType::Regular, "", 0o644),
+            ],
+        ),
+        (
+            "symlink",
+            vec![(zig.as_str(), EntryType::Symlink, "../../etc/passwd", 0o777)],
+        ),
+        (
+            "absolute-symlink",
+            vec![(zig.as_str(), EntryType::Symlink, "/etc/passwd", 0o777)],
+        ),
+        (
+            "hardlink",
+            vec![(zig.as_str(), EntryType::Link, "/etc/passwd", 0o644)],
+        ),
+        ("device", vec![(zig.as_str(), EntryType::Char, "", 0o644)]),
+        ("empty", vec![]),
+    ] {
+        let file = tarball(&format!("{}.tar.xz", name), &entries);
+        assert!(extract(&file).is_err(), "{} was extracted", name);
+    }
+}
+
+#[test]
+fn it_read_only() {
+    use std::fs::{metadata, write};
+    use temp_dir::TempDir;
+
+    let temp = TempDir::new().unwrap();
+    let root = temp.child("0.13.0");
+    create_dir_all(root.join("lib/std")).unwrap();
+    write(root.join("lib/std/std.zig"), "pub const x = 1;").unwrap();
+    write(root.join("lib/c.h"), "").unwrap();
+    let mode = |path: &Path| metadata(path).unwrap().permissions().mode() & 0o777;
+
+    set_read_only(&root, true, false).unwrap();
+    assert_eq!(mode(&root.join("lib/std/std.zig")) & 0o222, 0);
+    assert_eq!(mode(&root.join("lib/std")) & 0o222, 0);
+
+    let overlay = temp.child("overlay");
+    overlay_std(&root.join("lib"), &overlay).unwrap();
+    assert_ne!(mode(&overlay.join("std/std.zig")) & 0o200, 0);
+    assert!(overlay.join("c.h").is_symlink());
+    assert!(overlay_std(&root.join("lib"), &overlay).is_err());
+
+    make_removable(&root).unwrap();
+    assert_ne!(mode(&root.join("lib/std")) & 0o200, 0);
+    assert_eq!(mode(&root.join("lib/std/std.zig")) & 0o222, 0);
+
+    share_with_group(&root).unwrap();
+    set_read_only(&root, true, true).unwrap();
+    assert_eq!(mode(&root.join("lib/std")) & 0o222, 0);
+    make_removable(&root).unwrap();
+    assert_eq!(mode(&root.join("lib/std")) & 0o220, 0o220);
+    set_read_only(&root, false, true).unwrap();
+    assert_eq!(mode(&root.join("lib/std/std.zig")) & 0o220, 0o220);
+    remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,138 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs::read_dir;
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use semver::Version;
+use walkdir::WalkDir;
+
+use crate::error::Result;
+
+pub struct Install {
+    pub name: String,
+    pub path: PathBuf,
+    pub installed: SystemTime,
+    /// Access time of the `zig` binary, which is updated however it is run.
+    /// Coarse on `relatime` mounts and missing on `noatime` ones.
+    pub last_used: Option<SystemTime>,
+}
+
+impl Install {
+    /// The real version of the install, which differs from its name for `master`.
+    pub fn resolved(&self) -> Option<String> {
+        if Version::parse(&self.name).is_ok() {
+            return Some(self.name.clone());
+        }
+        let output = Command::new(self.path.join("zig"))
+            .arg("version")
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    pub fn size(&self) -> Result<u64> {
+        disk_usage(&self.path, &mut HashSet::new())
+    }
+}
+
+/// Every installed version, releases sorted oldest first followed by anything else like `master`.
+pub fn installs(install_location: &Path) -> Result<Vec<Install>> {
+    let mut installs = Vec::new();
+    let folders = match read_dir(install_location) {
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(installs),
+        x => x?,
+    };
+    for f in folders {
+        let folder = f?;
+        // Hidden directories hold zman's own files, like generated toolchain files
+        if !folder.file_type()?.is_dir() || folder.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let metadata = folder.metadata()?;
+        let last_used = folder
+            .path()
+            .join("zig")
+            .metadata()
+            .and_then(|x| x.accessed())
+            .ok();
+        installs.push(Install {
+            name: folder.file_name().to_string_lossy().to_string(),
+            path: folder.path(),
+            installed: metadata.created().or_else(|_| metadata.modified())?,
+            last_used,
+        });
+    }
+    installs.sort_by(
+        |a, b| match (Version::parse(&a.name), Version::parse(&b.name)) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => a.name.cmp(&b.name),
+        },
+    );
+    Ok(installs)
+}
+
+/// Bytes allocated on disk below `path`, skipping inodes already in `seen` so hardlinks count once.
+pub fn disk_usage(path: &Path, seen: &mut HashSet<(u64, u64)>) -> Result<u64> {
+    let mut total = 0;
+    for entry in WalkDir::new(path) {
+        let entry = match entry {
+            Err(e)
+                if e.io_error()
+                    .is_some_and(|x| x.kind() == ErrorKind::NotFound) =>
+            {
+                continue
+            }
+            x => x?,
+        };
+        let metadata = entry.metadata()?;
+        if seen.insert((metadata.dev(), metadata.ino())) {
+            total += metadata.blocks() * 512;
+        }
+    }
+    Ok(total)
+}
+
+pub fn date(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()[..10].to_string()
+}
+
+pub fn ago(time: SystemTime) -> String {
+    let elapsed = SystemTime::now()
+        .duration_since(time)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    match elapsed {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{} minutes ago", elapsed / 60),
+        3600..=86399 => format!("{} hours ago", elapsed / 3600),
+        _ => format!("{} days ago", elapsed / 86400),
+    }
+}
+
+#[test]
+fn it_installs() {
+    use std::fs::{create_dir_all, write};
+    use temp_dir::TempDir;
+
+    let temp = TempDir::new().unwrap();
+    for version in ["master", "0.12.0", "0.9.1"] {
+        create_dir_all(temp.child(version)).unwrap();
+        write(temp.child(version).join("zig"), "").unwrap();
+    }
+    write(temp.child("keep.txt"), "0.9.1").unwrap();
+    let names: Vec<String> = installs(temp.path())
+        .unwrap()
+        .into_iter()
+        .map(|x| x.name)
+        .collect();
+    assert_eq!(names, ["0.9.1", "0.12.0", "master"]);
+}
@@ -0,0 +1,78 @@
+//! Ctrl-C handling, so an interrupted install cleans up after itself instead of leaving partial
+//! files behind.
+//!
+//! Outside a [`guard`] a signal ends the process straight away as it normally would. Inside one it
+//! is only recorded, and the work in progress stops at the next [`check`] with an
+//! [`ErrorKind::Interrupted`](crate::ErrorKind::Interrupted) error, running destructors and
+//! cleanup on the way out. A second signal ends the process regardless.
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::error::{bail, Result};
+
+/// Signal received inside a guard, or 0.
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+static GUARDS: AtomicUsize = AtomicUsize::new(0);
+
+/// How often an interruptible future checks for a signal while it waits.
+const POLL: Duration = Duration::from_millis(100);
+
+extern "C" fn handle(signal: libc::c_int) {
+    if GUARDS.load(Ordering::SeqCst) == 0 || SIGNAL.swap(signal, Ordering::SeqCst) != 0 {
+        // SAFETY: _exit is async-signal-safe
+        unsafe { libc::_exit(128 + signal) };
+    }
+}
+
+/// Handles SIGINT and SIGTERM as described in the module documentation. Only meant for the
+/// `zman` binary, since it replaces whatever handler the process had.
+pub fn handle_signals() {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only touches atomics and calls _exit
+        unsafe { libc::signal(signal, handle as *const () as libc::sighandler_t) };
+    }
+}
+
+/// Defers signals until the returned guard is dropped, or until the work checks for them.
+pub fn guard() -> Guard {
+    GUARDS.fetch_add(1, Ordering::SeqCst);
+    Guard(())
+}
+
+pub struct Guard(());
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        GUARDS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The signal received inside a guard, like 2 for SIGINT.
+pub fn received() -> Option<i32> {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        x => Some(x),
+    }
+}
+
+/// Fails once a signal was received, for work to stop at a point it can clean up from.
+pub fn check() -> Result<()> {
+    if received().is_some() {
+        bail!(Interrupted, "Interrupted")
+    }
+    Ok(())
+}
+
+/// Awaits `future`, giving up on it when a signal is received.
+pub async fn interruptible<F: Future>(future: F) -> Result<F::Output> {
+    let mut future = pin!(future);
+    loop {
+        match tokio::time::timeout(POLL, &mut future).await {
+            Ok(x) => return Ok(x),
+            Err(_) => check()?,
+        }
+    }
+}
@@ -0,0 +1,748 @@
+//! Manage Zig installations.
+//!
+//! Build scripts can get a toolchain with [`ensure_zig`], which installs the requested version
+//! unless it is already present:
+//!
+//! ```no_run
+//! let zig = zman::ensure_zig("0.12.0", &zman::Options::default())?;
+//! std::process::Command::new(&zig.zig).args(["cc", "--version"]).status()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Nothing is printed unless [`Options::progress`] is set, and [`Progress::Json`] reports each step
+//! as a JSON event on stderr for frontends with their own progress display.
+
+use std::collections::BTreeMap;
+use std::env::consts::{ARCH, OS};
+use std::env::{var, var_os};
+use std::fs::{create_dir_all, remove_dir_all, set_permissions, write, Permissions};
+use std::os::unix::fs::symlink;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use directories::{BaseDirs, ProjectDirs};
+use semver::Version;
+use serde_json::Value;
+use temp_dir::TempDir;
+use tokio::runtime::Runtime;
+
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::cross::CrossFile;
+use crate::dedupe::{dedupe, Saved};
+use crate::error::{bail, Context};
+use crate::index::{fetch_ziglang_api, parse_ziglang_api, version_req, Release};
+use crate::install::{extract_and_copy, fetch_tarball};
+use crate::installs::installs;
+use crate::link::TargetDropins;
+use crate::manifest::Manifest;
+use crate::state::{DefaultVersion, InstallRecord, State, UserDefault};
+
+pub use crate::error::{Error, ErrorKind, Result};
+pub use crate::progress::{Event, Progress};
+
+pub mod cache;
+pub mod clean;
+pub mod config;
+pub mod cross;
+pub mod dedupe;
+pub mod doctor;
+mod download;
+mod error;
+pub mod global_cache;
+pub mod index;
+pub mod install;
+pub mod installs;
+pub mod interrupt;
+pub mod link;
+mod lock;
+pub mod manifest;
+pub mod pin;
+mod progress;
+mod space;
+pub mod state;
+
+#[derive(Clone, Default)]
+pub struct Options {
+    /// Install directory, otherwise taken from `ZMAN_HOME`, the config file or the user data
+    /// directory in that order.
+    pub install: Option<PathBuf>,
+    /// How status messages and progress are printed.
+    pub progress: Progress,
+    /// Use the shared install for every account on the machine, also turned on by setting
+    /// `ZMAN_SYSTEM`.
+    pub system: bool,
+    /// Leave out config values that cannot be used instead of failing, for commands that repair
+    /// the config file.
+    pub repair_config: bool,
+}
+
+/// An installed Zig version.
+pub struct Toolchain {
+    /// Name of the install directory, like `0.12.0` or `master`.
+    pub name: String,
+    /// Exact version, when known.
+    pub version: Option<String>,
+    pub root: PathBuf,
+    /// The `zig` executable.
+    pub zig: PathBuf,
+    /// The standard library and libc sources.
+    pub lib: PathBuf,
+}
+
+/// Directories and settings shared by every operation, resolved from [`Options`], the environment
+/// and the config file.
+pub struct Zman {
+    pub config: Config,
+    pub config_file: PathBuf,
+    pub install: PathBuf,
+    pub cache: PathBuf,
+    /// Where `zig` and drop-ins are linked by default.
+    pub link: PathBuf,
+    pub progress: Progress,
+    /// Whether this is the shared install, where files are made writable by its group.
+    pub system: bool,
+}
+
+/// Config file of the shared install.
+pub const SYSTEM_CONFIG: &str = "/etc/zman/config.toml";
+/// Default install directory of the shared install.
+pub const SYSTEM_INSTALL: &str = "/opt/zman";
+/// Default link directory of the shared install.
+pub const SYSTEM_LINK: &str = "/usr/local/bin";
+
+/// The user's own directory for executables, like `~/.local/bin`.
+pub fn user_link_dir() -> Result<PathBuf> {
+    BaseDirs::new()
+        .and_then(|x| x.executable_dir().map(|x| x.to_path_buf()))
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                "Local bin directory could not be found",
+            )
+        })
+}
+
+/// Link directory of the shared install, without anything else `Zman::new` would need. A system
+/// config file that cannot be read leaves the default.
+pub fn system_link_dir() -> PathBuf {
+    Config::load(Path::new(SYSTEM_CONFIG))
+        .ok()
+        .and_then(|x| x.link)
+        .unwrap_or_else(|| PathBuf::from(SYSTEM_LINK))
+}
+
+impl Zman {
+    pub fn new(options: &Options) -> Result<Zman> {
+        let system = options.system
+            || var("ZMAN_SYSTEM").is_ok_and(|x| !matches!(x.as_str(), "" | "0" | "false"));
+        let dirs = ProjectDirs::from("com", "", "zman").ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                "Default project directory could not be selected",
+            )
+        })?;
+        let config_file = match system {
+            true => PathBuf::from(SYSTEM_CONFIG),
+            false => dirs.config_dir().join("config.toml"),
+        };
+        let config = match options.repair_config {
+            true => {
+                let (config, ignored) = Config::load_valid(&config_file);
+                for reason in ignored {
+                    options
+                        .progress
+                        .message(&format!("{}, ignoring it", reason.trim_end()));
+                }
+                config
+            }
+            false => Config::load(&config_file)?,
+        };
+        for key in &config.unknown {
+            options.progress.message(&format!(
+                "Ignoring unknown key {:?} in {:?}",
+                key, config_file
+            ));
+        }
+        let default_install = match system {
+            true => PathBuf::from(SYSTEM_INSTALL),
+            false => dirs.data_dir().to_path_buf(),
+        };
+        let install = match (&options.install, var_os("ZMAN_HOME"), &config.install) {
+            (Some(x), _, _) => x.clone(),
+            (None, Some(x), _) => PathBuf::from(x),
+            (None, None, Some(x)) => x.clone(),
+            (None, None, None) => default_install,
+        };
+        let link = match (&config.link, system) {
+            (Some(x), _) => x.clone(),
+            (None, true) => PathBuf::from(SYSTEM_LINK),
+            (None, false) => user_link_dir()?,
+        };
+        let cache = match system {
+            // Kept with the installs so a single group owns everything shared
+            true => install.join(".cache"),
+            false => dirs.cache_dir().to_path_buf(),
+        };
+        Ok(Zman {
+            config,
+            config_file,
+            install,
+            cache,
+            link,
+            progress: options.progress,
+            system,
+        })
+    }
+
+    /// Waits for other zman processes changing the install directory to finish, and keeps them
+    /// waiting until the returned lock is dropped.
+    pub fn lock(&self) -> Result<lock::Lock> {
+        if self.system && !self.install.exists() {
+            create_dir_all(&self.install)?;
+            // New directories inherit the group of the install directory
+            set_permissions(&self.install, Permissions::from_mode(0o2775))?;
+        }
+        create_dir_all(&self.install)
+            .with_context(|| format!("Cannot create install directory {:?}", self.install))?;
+        lock::Lock::acquire(&self.install.join(".lock"), self.progress)
+    }
+
+    pub fn state(&self) -> Result<State> {
+        State::load(&self.install, &self.link)
+    }
+
+    pub fn tarballs(&self) -> Result<Cache> {
+        Ok(Cache::new(
+            self.cache.join("tarballs"),
+            self.config.cache_limit()?,
+        ))
+    }
+
+    /// The install with the given directory name, which may not exist.
+    pub fn toolchain(&self, name: &str, state: &State) -> Toolchain {
+        let root = self.install.join(name);
+        Toolchain {
+            name: name.to_string(),
+            version: state.installs.get(name).map(|x| x.version.clone()),
+            zig: root.join("zig"),
+            lib: root.join("lib"),
+            root,
+        }
+    }
+
+    /// Looks up the newest installed version matching `spec` without going online. `latest` is
+    /// never found since it can only be resolved from the download index.
+    pub fn find(&self, spec: &str) -> Result<Option<Toolchain>> {
+        let name = match spec {
+            "latest" => return Ok(None),
+            "master" => Some(spec.to_string()),
+            spec => {
+                let req = version_req(spec)?;
+                installs(&self.install)?
+                    .into_iter()
+                    .filter_map(|x| Version::parse(&x.name).ok())
+                    .filter(|x| req.matches(x))
+                    .max()
+                    .map(|x| x.to_string())
+            }
+        };
+        let Some(name) = name else {
+            return Ok(None);
+        };
+        let toolchain = self.toolchain(&name, &self.state()?);
+        Ok(toolchain.zig.try_exists()?.then_some(toolchain))
+    }
+
+    /// ziglang.org's download index, or the last one fetched when it cannot be reached.
+    pub fn index(&self) -> Result<Value> {
+        let rt = Runtime::new()?;
+        rt.block_on(fetch_ziglang_api(
+            &download::client()?,
+            &self.cache,
+            self.progress,
+        ))
+    }
+
+    /// Finds the release for `spec`, looking exact versions up in the cache before
+    /// ziglang.org's download index.
+    pub fn resolve(&self, spec: &str) -> Result<Release> {
+        let release = match self.resolve_cached(spec)? {
+            Some(x) => x,
+            None => parse_ziglang_api(&self.index()?, spec)?,
+        };
+        self.progress.emit(&Event::Resolve {
+            spec,
+            version: &release.version,
+        });
+        Ok(release)
+    }
+
+    /// Finds an exact version in the cached download index, by the checksum recorded when it was
+    /// installed or by a tarball seeded into the cache.
+    fn resolve_cached(&self, spec: &str) -> Result<Option<Release>> {
+        let Ok(version) = Version::parse(spec) else {
+            return Ok(None);
+        };
+        if let Some(release) =
+            index::cached(&self.cache)?.and_then(|x| parse_ziglang_api(&x, spec).ok())
+        {
+            return Ok(Some(release));
+        }
+        let tarballs = self.tarballs()?;
+        let recorded = self
+            .state()?
+            .installs
+            .into_values()
+            .filter(|x| x.version == spec)
+            .find_map(|x| x.shasum);
+        let shasum = match recorded {
+            Some(x) => x,
+            None => match tarballs.seeded(spec)? {
+                Some(x) => {
+                    self.progress.message(&format!(
+                        "Using the tarball of {} seeded into the cache, which cannot be checked \
+                         against ziglang.org's checksum without the download index",
+                        spec
+                    ));
+                    x
+                }
+                None => return Ok(None),
+            },
+        };
+        // Tarballs were renamed from zig-linux-x86_64-<version> to zig-x86_64-linux-<version>
+        let name = match version >= Version::new(0, 14, 1) {
+            true => format!("zig-{}-{}-{}.tar.xz", ARCH, OS, spec),
+            false => format!("zig-{}-{}-{}.tar.xz", OS, ARCH, spec),
+        };
+        Ok(Some(Release {
+            // Only downloaded from if the cached tarball goes missing, and checked against
+            // `shasum` as always
+            url: format!("https://ziglang.org/download/{}/{}", spec, name),
+            version: spec.to_string(),
+            size: None,
+            shasum,
+        }))
+    }
+
+    /// The install of a release, if it is already installed.
+    pub fn installed(&self, release: &Release, spec: &str) -> Result<Option<Toolchain>> {
+        let state = self.state()?;
+        let name = release.install_name(spec);
+        let toolchain = self.toolchain(&name, &state);
+        let installed = match state.installs.get(&name) {
+            Some(x) if spec == "master" => x.version == release.version,
+            _ => spec != "master",
+        };
+        let exists = toolchain
+            .zig
+            .try_exists()
+            .with_context(|| format!("Cannot check if {:?} already downloaded", release.version))?;
+        Ok((installed && exists).then_some(toolchain))
+    }
+
+    /// Installs a release unless it already is, recording it in the state file.
+    pub fn install(&self, release: &Release, spec: &str) -> Result<Toolchain> {
+        if let Some(toolchain) = self.installed(release, spec)? {
+            self.progress.message(&format!(
+                "Zig version {} already downloaded",
+                release.version
+            ));
+            return Ok(toolchain);
+        }
+        self.reinstall(release, spec)
+    }
+
+    /// Installs a release even if it already is. What is installed is only replaced once the
+    /// tarball has been downloaded and verified.
+    pub fn reinstall(&self, release: &Release, spec: &str) -> Result<Toolchain> {
+        let _guard = interrupt::guard();
+        interrupt::check()?;
+        let mut state = self.state()?;
+        let name = release.install_name(spec);
+        let toolchain = self.toolchain(&name, &state);
+
+        let tarballs = self.tarballs()?;
+        let cached = tarballs.size(&release.shasum);
+        if let Some(size) = cached.or(release.size) {
+            let unpacked = size.checked_mul(space::UNPACKED_RATIO).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::Invalid,
+                    format!("Tarball size {} of {} is too large", size, release.version),
+                )
+            })?;
+            space::ensure_space(&[
+                (&self.cache, if cached.is_some() { 0 } else { size }),
+                (&std::env::temp_dir(), unpacked),
+                (&self.install, unpacked),
+            ])?;
+        }
+        let rt = Runtime::new()?;
+        let tarxz = rt.block_on(fetch_tarball(
+            &download::client()?,
+            &tarballs,
+            &self.config.mirrors,
+            release,
+            self.progress,
+        ))?;
+        let temp = TempDir::with_prefix("zman")?;
+        extract_and_copy(
+            &tarxz,
+            temp.child(&name),
+            &toolchain.root,
+            &release.version,
+            self.progress,
+        )?;
+        if self.system {
+            install::share_with_group(&toolchain.root)?;
+        }
+        Manifest::create(&toolchain.root, &release.version, &release.shasum)?
+            .save(&self.install, &name)?;
+        if self.config.read_only {
+            install::set_read_only(&toolchain.root, true, self.system)?;
+        }
+        state.installs.insert(
+            name.clone(),
+            InstallRecord {
+                version: release.version.clone(),
+                installed: SystemTime::now(),
+                shasum: Some(release.shasum.clone()),
+            },
+        );
+        state.save(&self.install)?;
+        self.link_versions(&mut state)?;
+        if self.config.dedupe {
+            let saved = self.dedupe(self.config.reflink)?;
+            self.progress.message(&saved.to_string());
+        }
+        Ok(self.toolchain(&name, &state))
+    }
+
+    /// Installs `name` again from the tarball it was installed from when that is still cached,
+    /// otherwise from the download index, which for `master` may be a newer build.
+    pub fn repair(&self, name: &str) -> Result<Toolchain> {
+        let cached = match Manifest::load(&self.install, name)? {
+            Some(x) if self.tarballs()?.get(&x.shasum)?.is_some() => Some(Release {
+                version: x.version,
+                url: String::new(),
+                shasum: x.shasum,
+                size: None,
+            }),
+            _ => None,
+        };
+        let release = match cached {
+            Some(x) => x,
+            None => self.resolve(name)?,
+        };
+        self.reinstall(&release, name)
+    }
+
+    /// Links a toolchain into `link` with the given drop-ins and records it as the default.
+    pub fn set_default(
+        &self,
+        spec: &str,
+        toolchain: &Toolchain,
+        link: &Path,
+        dropins: &[String],
+    ) -> Result<()> {
+        // Links are swapped as a whole, so they are never left half changed
+        let _guard = interrupt::guard();
+        interrupt::check()?;
+        let mut state = self.state()?;
+        link::make_symlink(&toolchain.root, link, dropins, &state.targets)?;
+        self.progress.emit(&Event::Link { dir: link });
+        for cross in &state.cross_files {
+            self.write_cross_file(cross, &state, toolchain)?;
+        }
+        state.default = Some(DefaultVersion {
+            spec: spec.to_string(),
+            install: toolchain.name.clone(),
+        });
+        state.add_link(link, !dropins.is_empty());
+        state.save(&self.install)?;
+        self.link_versions(&mut state)
+    }
+
+    /// Hardlinks identical files across installs. Directories of read-only installs are made
+    /// writable while links are made in them.
+    pub fn dedupe(&self, reflink: bool) -> Result<Saved> {
+        if !self.config.read_only {
+            return dedupe(&self.install, reflink);
+        }
+        let installs = installs(&self.install)?;
+        for install in &installs {
+            install::make_removable(&install.path)?;
+        }
+        let saved = dedupe(&self.install, reflink);
+        for install in &installs {
+            install::set_read_only(&install.path, true, self.system)?;
+        }
+        saved
+    }
+
+    /// Makes every install read-only or writable again, following the `read_only` setting.
+    pub fn protect_installs(&self) -> Result<()> {
+        for install in installs(&self.install)? {
+            install::set_read_only(&install.path, self.config.read_only, self.system)
+                .with_context(|| format!("Cannot change permissions of {:?}", install.path))?;
+        }
+        Ok(())
+    }
+
+    /// Links `zig-<series>` to the newest install of every release series and master into each
+    /// link directory, along with versioned drop-ins where `zig` has drop-ins, when
+    /// `versioned_links` is set. Links made before are removed first, so turning the setting off
+    /// removes them all.
+    pub fn link_versions(&self, state: &mut State) -> Result<()> {
+        let dirs = self.link_dirs(state);
+        let old = std::mem::take(&mut state.versioned);
+        let mut linked: Vec<Vec<String>> = vec![Vec::new(); dirs.len()];
+        if self.config.versioned_links {
+            let mut newest: BTreeMap<String, (Option<Version>, String)> = BTreeMap::new();
+            for install in installs(&self.install)? {
+                let Some(series) = link::series(&install.name) else {
+                    continue;
+                };
+                let version = Version::parse(&install.name).ok();
+                if newest.get(&series).is_none_or(|(x, _)| *x < version) {
+                    newest.insert(series, (version, install.name));
+                }
+            }
+            let dropins = self.config.dropins();
+            for (dir, linked) in dirs.iter().zip(&mut linked) {
+                let dropins = match state.links.iter().find(|x| x.dir == *dir) {
+                    Some(x) if !x.dropins => &[][..],
+                    _ => &dropins[..],
+                };
+                for (series, (_, name)) in &newest {
+                    let names =
+                        link::add_versioned(&self.install.join(name), dir, series, dropins)?;
+                    for x in names {
+                        if !state.versioned.contains(&x) {
+                            state.versioned.push(x.clone());
+                        }
+                        linked.push(x);
+                    }
+                }
+            }
+        }
+        // Links still wanted were replaced in place, so they are never missing meanwhile
+        for (dir, linked) in dirs.iter().zip(&linked) {
+            link::rm_dropins(dir, old.iter().filter(|x| !linked.contains(x)).cloned())?;
+        }
+        if old != state.versioned {
+            state.save(&self.install)?;
+        }
+        Ok(())
+    }
+
+    /// Existing directories `zig` is linked in, where target drop-ins belong.
+    fn link_dirs(&self, state: &State) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = state.links.iter().map(|x| x.dir.clone()).collect();
+        if !dirs.contains(&self.link) {
+            dirs.push(self.link.clone());
+        }
+        dirs.retain(|x| x.is_dir());
+        dirs
+    }
+
+    /// Links a toolchain into the user's own `link` directory in place of the system default,
+    /// for users of a shared install who want another version.
+    pub fn set_user_default(
+        &self,
+        spec: &str,
+        toolchain: &Toolchain,
+        link: &Path,
+        dropins: &[String],
+    ) -> Result<()> {
+        // Links are swapped as a whole, so they are never left half changed
+        let _guard = interrupt::guard();
+        interrupt::check()?;
+        let mut state = self.state()?;
+        link::make_symlink(&toolchain.root, link, dropins, &[])?;
+        self.progress.emit(&Event::Link { dir: link });
+        let uid = current_uid();
+        state.user_defaults.retain(|x| x.uid != uid);
+        state.user_defaults.push(UserDefault {
+            uid,
+            user: var("USER").ok(),
+            spec: spec.to_string(),
+            install: toolchain.name.clone(),
+            link: link.to_path_buf(),
+            dropins: !dropins.is_empty(),
+        });
+        state.save(&self.install)
+    }
+
+    /// Unlinks the user's own default so the system default is used again, returning whether
+    /// there was one.
+    pub fn reset_user_default(&self) -> Result<bool> {
+        let mut state = self.state()?;
+        let uid = current_uid();
+        let Some(i) = state.user_defaults.iter().position(|x| x.uid == uid) else {
+            return Ok(false);
+        };
+        let removed = state.user_defaults.remove(i);
+        link::remove_if_exists(&removed.link.join("zig"))?;
+        if removed.dropins {
+            link::rm_dropins(
+                &removed.link,
+                self.config
+                    .dropins()
+                    .iter()
+                    .map(|x| link::DROPIN_PREFIX.to_string() + x),
+            )?;
+        }
+        state.save(&self.install)?;
+        Ok(true)
+    }
+
+    /// Links drop-ins for a target into every link directory, replacing any for the same target.
+    pub fn add_target(&self, target: TargetDropins) -> Result<()> {
+        cross::parse_target(&target.target)?;
+        let mut state = self.state()?;
+        for dir in self.link_dirs(&state) {
+            link::add_dropins(&dir, target.names())?;
+        }
+        state.targets.retain(|x| x.target != target.target);
+        state.targets.push(target);
+        state.save(&self.install)
+    }
+
+    /// Unlinks the drop-ins for a target from every link directory.
+    pub fn remove_target(&self, target: &str) -> Result<()> {
+        let mut state = self.state()?;
+        let Some(i) = state.targets.iter().position(|x| x.target == target) else {
+            bail!(NotFound, "No drop-ins for target {}", target)
+        };
+        let removed = state.targets.remove(i);
+        for dir in self.link_dirs(&state) {
+            link::rm_dropins(&dir, removed.names())?;
+        }
+        // Cross files cannot work without the target's drop-ins
+        if state.cross_files.iter().any(|x| x.target == target) {
+            state.cross_files.retain(|x| x.target != target);
+            remove_dir_all(self.install.join(cross::DIR).join(target))?;
+        }
+        state.save(&self.install)
+    }
+
+    /// Links drop-ins for the Zig target matching a Rust target into the link directory, unless
+    /// they are there already, and returns the linker and archiver Cargo should use.
+    pub fn cargo_linker(&self, rust_target: &str) -> Result<(PathBuf, PathBuf)> {
+        let (target, cpu) = cross::zig_target(rust_target)?;
+        let dropins = match self
+            .state()?
+            .targets
+            .into_iter()
+            .find(|x| x.target == target)
+        {
+            Some(x) => x,
+            None => TargetDropins {
+                target,
+                cpu,
+                flags: Vec::new(),
+            },
+        };
+        let linker = self.link.join(format!("{}-cc", dropins.target));
+        let ar = self.link.join(format!("{}-ar", dropins.target));
+        if !linker.exists() || !ar.exists() {
+            create_dir_all(&self.link)?;
+            self.add_target(dropins)?;
+        }
+        Ok((linker, ar))
+    }
+
+    /// Writes a cross file into a directory of its own along with the target's drop-ins and the
+    /// `zig` they run.
+    fn write_cross_file(
+        &self,
+        cross: &CrossFile,
+        state: &State,
+        toolchain: &Toolchain,
+    ) -> Result<PathBuf> {
+        let dropins = state
+            .targets
+            .iter()
+            .find(|x| x.target == cross.target)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("No drop-ins for target {}", cross.target),
+                )
+            })?;
+        let dir = self.install.join(cross::DIR).join(&cross.target);
+        create_dir_all(&dir)?;
+        link::remove_if_exists(&dir.join("zig"))?;
+        symlink(&toolchain.zig, dir.join("zig"))?;
+        link::add_dropins(&dir, dropins.names())?;
+        let file = dir.join(cross.file_name());
+        let version = toolchain.version.as_deref().unwrap_or(&toolchain.name);
+        write(&file, cross.render(&dir, dropins, version)?)
+            .with_context(|| format!("Cannot write {:?}", file))?;
+        Ok(file)
+    }
+
+    /// Generates a CMake or Meson file for the default version, rewritten whenever it changes.
+    /// The target gets drop-ins unless it has some, which are replaced when `cpu` is given.
+    pub fn add_cross_file(&self, cross: CrossFile, cpu: Option<String>) -> Result<PathBuf> {
+        let existing = self
+            .state()?
+            .targets
+            .into_iter()
+            .find(|x| x.target == cross.target);
+        match existing {
+            Some(x) if cpu.is_none() || x.cpu == cpu => {}
+            x => self.add_target(TargetDropins {
+                target: cross.target.clone(),
+                cpu,
+                flags: x.map(|x| x.flags).unwrap_or_default(),
+            })?,
+        }
+        let mut state = self.state()?;
+        let Some(name) = state.default_install() else {
+            bail!(NotFound, "No default version. Set one with `zman default`")
+        };
+        let file = self.write_cross_file(&cross, &state, &self.toolchain(name, &state))?;
+        if !state.cross_files.contains(&cross) {
+            state.cross_files.push(cross);
+            state.save(&self.install)?;
+        }
+        Ok(file)
+    }
+
+    /// Deletes an install and forgets about it.
+    pub fn remove(&self, name: &str, state: &mut State) -> Result<()> {
+        let path = self.install.join(name);
+        if !path.try_exists()? {
+            bail!(NotFound, "Version {} is not installed", name)
+        }
+        install::make_removable(&path)?;
+        remove_dir_all(&path).with_context(|| format!("Cannot remove {:?}", path))?;
+        Manifest::remove(&self.install, name)?;
+        state.remove_install(name);
+        state.save(&self.install)?;
+        self.link_versions(state)
+    }
+}
+
+/// The real user ID of the process, which owns its entry in a shared install's user defaults.
+pub fn current_uid() -> u32 {
+    // SAFETY: getuid cannot fail
+    unsafe { libc::getuid() }
+}
+
+/// Returns the toolchain for `spec`, which is a version or range like `0.12`, `latest` or `master`,
+/// installing it first if no installed version matches.
+pub fn ensure_zig(spec: &str, options: &Options) -> Result<Toolchain> {
+    let zman = Zman::new(options)?;
+    if spec != "master" {
+        if let Some(x) = zman.find(spec)? {
+            return Ok(x);
+        }
+    }
+    let release = zman.resolve(spec)?;
+    let _lock = zman.lock()?;
+    zman.install(&release, spec)
+}
@@ -0,0 +1,284 @@
+use std::env::{current_exe, split_paths, var_os};
+use std::fs::{create_dir_all, remove_file, rename};
+use std::io::ErrorKind;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::error::{bail, Context, Result};
+
+pub const DROPIN_PREFIX: &str = "zig-";
+
+/// Tools linked for every target drop-in set, as `<target>-<tool>`.
+pub const TARGET_TOOLS: [&str; 4] = ["cc", "c++", "ar", "ranlib"];
+
+/// Drop-ins compiling for another target, like `aarch64-linux-musl-cc`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TargetDropins {
+    /// Zig target triple, like `aarch64-linux-musl`.
+    pub target: String,
+    pub cpu: Option<String>,
+    /// Extra flags passed to `cc` and `c++`.
+    #[serde(default)]
+    pub flags: Vec<String>,
+}
+
+impl TargetDropins {
+    pub fn names(&self) -> Vec<String> {
+        TARGET_TOOLS
+            .iter()
+            .map(|x| format!("{}-{}", self.target, x))
+            .collect()
+    }
+
+    /// The Zig subcommand and arguments the drop-in `name` stands for, if it is one of these.
+    pub fn args(&self, name: &str) -> Option<Vec<String>> {
+        let tool = name.strip_prefix(&self.target)?.strip_prefix('-')?;
+        match tool {
+            "cc" | "c++" => {
+                let mut args = vec![tool.to_string(), "-target".to_string(), self.target.clone()];
+                args.extend(self.cpu.iter().map(|x| format!("-mcpu={}", x)));
+                args.extend(self.flags.iter().cloned());
+                Some(args)
+            }
+            "ar" | "ranlib" => Some(vec![tool.to_string()]),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn remove_if_exists(path: &Path) -> Result<()> {
+    match remove_file(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        x => Ok(x?),
+    }
+}
+
+/// Links each drop-in name to the zman executable, which runs the matching Zig subcommand when
+/// invoked under that name.
+pub fn add_dropins(destination: &Path, names: impl IntoIterator<Item = String>) -> Result<()> {
+    let launcher = current_exe().context("Cannot find the zman executable to link drop-ins to")?;
+    for x in names {
+        replace_symlink(&launcher, &destination.join(x))?;
+    }
+    Ok(())
+}
+
+/// Points the link at `path` to `original` in a single rename, so it is never missing or broken
+/// even if zman is killed halfway.
+fn replace_symlink(original: &Path, path: &Path) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.zman-new", name));
+    match remove_file(&temp) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    };
+    symlink(original, &temp)?;
+    rename(&temp, path)
+}
+
+pub fn rm_dropins(destination: &Path, names: impl IntoIterator<Item = String>) -> Result<()> {
+    for x in names {
+        remove_if_exists(&destination.join(x))?;
+    }
+    Ok(())
+}
+
+fn dropin_names(dropins: &[String]) -> impl Iterator<Item = String> + '_ {
+    dropins.iter().map(|x| DROPIN_PREFIX.to_string() + x)
+}
+
+/// Links `zig` from the install at `source` into `destination`, along with `zig-<name>` drop-ins
+/// and target drop-ins, replacing what was linked there before.
+pub fn make_symlink(
+    source: &Path,
+    destination: &Path,
+    dropins: &[String],
+    targets: &[TargetDropins],
+) -> Result<()> {
+    create_dir_all(destination)?;
+    let replaced = destination.join("zig").is_symlink();
+    match replace_symlink(&source.join("zig"), &destination.join("zig")) {
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            bail!(Permission, "Permission denied to create symlink at {:?}. Try passing a custom symlink directory with --link option, or --system as root for a shared install", destination)
+        }
+        x => x?,
+    };
+    if replaced {
+        let defaults = config::DROPINS.map(String::from);
+        let chosen = dropin_names(dropins).collect::<Vec<_>>();
+        rm_dropins(
+            destination,
+            dropin_names(&defaults).filter(|x| !chosen.contains(x)),
+        )?;
+    }
+    add_dropins(destination, dropin_names(dropins))?;
+    add_dropins(destination, targets.iter().flat_map(TargetDropins::names))?;
+    Ok(())
+}
+
+/// The series a versioned link is named after, like `0.12` for `0.12.1` or `master`.
+pub fn series(install: &str) -> Option<String> {
+    match install {
+        "master" => Some(install.to_string()),
+        x => Version::parse(x)
+            .ok()
+            .map(|x| format!("{}.{}", x.major, x.minor)),
+    }
+}
+
+/// Splits what follows `zig-` in a drop-in name into the series it runs, if any, and the
+/// subcommand, so `0.12-cc` is `cc` for Zig 0.12. `None` for a versioned `zig` like `0.12`.
+fn split_series(name: &str) -> Option<(Option<&str>, &str)> {
+    if name.starts_with(|x: char| x.is_ascii_digit()) || name.starts_with("master-") {
+        let (series, subcommand) = name.split_once('-')?;
+        Some((Some(series), subcommand))
+    } else if name == "master" {
+        None
+    } else {
+        Some((None, name))
+    }
+}
+
+/// The Zig subcommand a drop-in stands for, when zman was invoked as one like `zig-cc` or
+/// `zig-0.12-cc`.
+pub fn dropin_subcommand(argv0: &Path) -> Option<String> {
+    let name = argv0.file_name()?.to_str()?.strip_prefix(DROPIN_PREFIX)?;
+    split_series(name)
+        .map(|(_, x)| x)
+        .filter(|x| !x.is_empty())
+        .map(String::from)
+}
+
+/// The series a versioned drop-in like `zig-0.12-cc` runs.
+pub fn dropin_series(argv0: &Path) -> Option<String> {
+    let name = argv0.file_name()?.to_str()?.strip_prefix(DROPIN_PREFIX)?;
+    split_series(name)?.0.map(String::from)
+}
+
+/// Links `zig-<series>` to the `zig` of the install at `source`, and `zig-<series>-<name>`
+/// drop-ins running it.
+pub fn add_versioned(
+    source: &Path,
+    destination: &Path,
+    series: &str,
+    dropins: &[String],
+) -> Result<Vec<String>> {
+    let zig = format!("{}{}", DROPIN_PREFIX, series);
+    replace_symlink(&source.join("zig"), &destination.join(&zig))?;
+    let names: Vec<String> = dropins.iter().map(|x| format!("{}-{}", zig, x)).collect();
+    add_dropins(destination, names.iter().cloned())?;
+    Ok([zig].into_iter().chain(names).collect())
+}
+
+/// Whether zman was invoked as a target drop-in like `aarch64-linux-musl-cc`.
+pub fn is_target_dropin(argv0: &Path) -> bool {
+    argv0
+        .file_name()
+        .and_then(|x| x.to_str())
+        .and_then(|x| x.rsplit_once('-'))
+        .is_some_and(|(_, tool)| TARGET_TOOLS.contains(&tool))
+}
+
+/// The `zig` linked next to the drop-in invoked as `argv0`, which is looked up in `PATH` when
+/// invoked by name alone.
+pub fn dropin_zig(argv0: &Path) -> Result<PathBuf> {
+    let invoked = if argv0.components().count() > 1 {
+        Some(argv0.to_path_buf())
+    } else {
+        var_os("PATH").and_then(|x| {
+            split_paths(&x)
+                .map(|dir| dir.join(argv0))
+                .find(|x| x.exists())
+        })
+    };
+    match invoked.as_deref().and_then(Path::parent) {
+        Some(dir) => Ok(dir.join("zig")),
+        None => bail!(NotFound, "Cannot find where drop-in {:?} is linked", argv0),
+    }
+}
+
+#[test]
+fn it_dropin() {
+    assert_eq!(
+        dropin_subcommand(Path::new("/home/me/.local/bin/zig-c++")).as_deref(),
+        Some("c++")
+    );
+    assert_eq!(dropin_subcommand(Path::new("zman")), None);
+    assert_eq!(dropin_subcommand(Path::new("zig-")), None);
+    assert_eq!(
+        dropin_subcommand(Path::new("bin/zig-0.12-cc")).as_deref(),
+        Some("cc")
+    );
+    assert_eq!(
+        dropin_series(Path::new("bin/zig-0.12-cc")).as_deref(),
+        Some("0.12")
+    );
+    assert_eq!(
+        dropin_series(Path::new("zig-master-c++")).as_deref(),
+        Some("master")
+    );
+    assert_eq!(dropin_series(Path::new("zig-cc")), None);
+    assert_eq!(dropin_subcommand(Path::new("zig-0.12")), None);
+    assert_eq!(dropin_subcommand(Path::new("zig-master")), None);
+    assert_eq!(series("0.12.1").as_deref(), Some("0.12"));
+    assert_eq!(series("master").as_deref(), Some("master"));
+    assert_eq!(series(".toolchains"), None);
+    assert!(is_target_dropin(Path::new("bin/x86_64-windows-gnu-c++")));
+    assert!(!is_target_dropin(Path::new("/usr/bin/zman")));
+    assert_eq!(
+        dropin_zig(Path::new("/home/me/.local/bin/zig-cc")).unwrap(),
+        Path::new("/home/me/.local/bin/zig")
+    );
+
+    let target = TargetDropins {
+        target: "aarch64-linux-musl".to_string(),
+        cpu: Some("cortex_a72".to_string()),
+        flags: vec!["-static".to_string()],
+    };
+    assert_eq!(
+        target.args("aarch64-linux-musl-cc").unwrap(),
+        [
+            "cc",
+            "-target",
+            "aarch64-linux-musl",
+            "-mcpu=cortex_a72",
+            "-static"
+        ]
+    );
+    assert_eq!(target.args("aarch64-linux-musl-ar").unwrap(), ["ar"]);
+    assert_eq!(target.args("aarch64-linux-gnu-cc"), None);
+    assert_eq!(target.args("aarch64-linux-musl-ld"), None);
+}
+
+#[test]
+fn it_symlink() {
+    make_symlink(
+        &PathBuf::from("/tmp/zig/0.11.0"),
+        &PathBuf::from("/tmp/bin"),
+        &config::DROPINS.map(String::from),
+        &[],
+    )
+    .unwrap();
+}
+
+#[test]
+fn it_replace_symlink() {
+    use std::fs::read_link;
+    use temp_dir::TempDir;
+
+    let temp = TempDir::new().unwrap();
+    let bin = temp.child("bin");
+    let dropins = ["cc".to_string()];
+    make_symlink(&temp.child("0.11.0"), &bin, &dropins, &[]).unwrap();
+    make_symlink(&temp.child("0.12.0"), &bin, &[], &[]).unwrap();
+    assert_eq!(
+        read_link(bin.join("zig")).unwrap(),
+        temp.child("0.12.0/zig")
+    );
+    assert!(!bin.join("zig-cc").is_symlink());
+    assert!(!bin.join(".zig.zman-new").is_symlink());
+}
@@ -0,0 +1,43 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+
+use crate::error::{Context, Result};
+use crate::progress::Progress;
+
+/// An exclusive lock on a file, released when dropped or when the process exits however it does.
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    pub fn acquire(path: &Path, progress: Progress) -> Result<Lock> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Cannot lock {:?}", path))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                progress.message("Waiting for another zman to finish");
+                file.lock()?;
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        Ok(Lock { _file: file })
+    }
+}
+
+#[test]
+fn it_lock() {
+    use temp_dir::TempDir;
+
+    let temp = TempDir::new().unwrap();
+    let path = temp.child(".lock");
+    let lock = Lock::acquire(&path, Progress::Hidden).unwrap();
+    let other = File::open(&path).unwrap();
+    assert!(matches!(other.try_lock(), Err(TryLockError::WouldBlock)));
+    drop(lock);
+    assert!(other.try_lock().is_ok());
+}
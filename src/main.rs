@@ -1,50 +1,69 @@
-use std::env::consts::{ARCH, OS};
-use std::env::var;
-use std::fs::{
-    create_dir_all, read_dir, read_to_string, remove_dir_all, remove_file, set_permissions, write,
-    File, Permissions,
-};
-use std::io::{ErrorKind, Read};
-use std::os::unix::fs::{symlink, PermissionsExt};
+use std::collections::HashSet;
+use std::env::{args_os, current_dir, var};
+use std::io::{stderr, stdin, IsTerminal};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, ExitCode};
+use std::time::{Duration, SystemTime};
 
-use clap::{value_parser, ColorChoice, Parser, Subcommand, ValueHint};
+use clap::{value_parser, ColorChoice, Parser, Subcommand, ValueEnum, ValueHint};
 use console::Term;
-use directories::{BaseDirs, ProjectDirs};
-use eyre::{bail, ensure, eyre, OptionExt, Result, WrapErr};
-use fs_extra::dir::{copy, CopyOptions};
-use reqwest::Client;
-use semver::{Version, VersionReq};
-use serde_json::Value;
-use sha2::{Digest, Sha256};
-use tar::Archive;
-use temp_dir::TempDir;
-use tokio::runtime::Runtime;
-use xz2::read::XzDecoder;
-
-use download::download_file;
+use eyre::{bail, ensure, OptionExt, Result, WrapErr};
+use indicatif::HumanBytes;
+use serde_json::{json, Value};
 
-mod download;
+use zman::cross::{self, BuildSystem, CrossFile};
+use zman::global_cache::{self, parse_size};
+use zman::installs::{self, disk_usage, installs};
+use zman::link::TargetDropins;
+use zman::manifest::Manifest;
+use zman::{
+    clean, config, doctor, index, interrupt, link, pin, state, ErrorKind, Event, Options, Progress,
+    Zman,
+};
 
 #[derive(Parser)]
 #[command(
     version, about, color = ColorChoice::Auto, help_expected = true, disable_help_subcommand = true, long_about = None
 )]
 struct Cli {
+    #[arg(
+        long, global = true, env = "ZMAN_HOME", value_parser = value_parser ! (PathBuf), value_hint = ValueHint::DirPath, value_name = "DIR"
+    )]
+    /// Custom installation directory.
+    install: Option<PathBuf>,
+    #[arg(long, global = true)]
+    /// Print a JSON document instead of text, for list, ls-remote, info, fetch, default, clean,
+    /// du, doctor, verify and edit-std.
+    json: bool,
+    #[arg(long, global = true, value_enum, default_value_t = ProgressMode::Auto, value_name = "MODE")]
+    /// How progress is shown. Auto draws bars on a terminal and prints plain updates otherwise.
+    progress: ProgressMode,
+    #[arg(long, global = true)]
+    /// Use the install shared by every account, in /opt/zman with its config in /etc/zman and
+    /// links in /usr/local/bin. Also turned on by setting ZMAN_SYSTEM.
+    system: bool,
     #[command(subcommand)]
     cmd: Cmd,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ProgressMode {
+    /// Bars on a terminal, plain otherwise. Nothing with --json.
+    Auto,
+    /// Progress bars redrawn in place.
+    Bar,
+    /// Periodic plain text updates.
+    Plain,
+    /// Newline-delimited JSON events on stderr.
+    Json,
+    /// No progress or status messages.
+    None,
+}
+
 #[derive(Subcommand)]
 enum Cmd {
     /// Download and set a Zig version as default.
     Default {
-        #[arg(
-            long, value_parser = value_parser ! (PathBuf), value_hint = ValueHint::DirPath, value_name = "DIR"
-        )]
-        /// Custom installation directory.
-        install: Option<PathBuf>,
         #[arg(
             long, value_parser = value_parser ! (PathBuf), value_hint = ValueHint::DirPath, value_name = "DIR"
         )]
@@ -53,34 +72,129 @@ enum Cmd {
         #[arg(long)]
         /// Do no create shims like zig-cc and zig-c++ for Zig drop-in replacements.
         no_dropins: bool,
+        #[arg(long)]
+        /// Hardlink files identical to ones in other installed versions.
+        dedupe: bool,
+        #[arg(long)]
+        /// With --system, link the version into your own bin directory in place of the system
+        /// default. Give system as the version to use the system default again.
+        user: bool,
         /// Exact version number or use latest for latest release or master for latest build.
         version: String,
     },
     /// Download a zig version.
     Fetch {
-        #[arg(
-            long, value_parser = value_parser ! (PathBuf), value_hint = ValueHint::DirPath, value_name = "DIR"
-        )]
-        /// Custom installation directory.
-        install: Option<PathBuf>,
+        #[arg(long)]
+        /// Hardlink files identical to ones in other installed versions.
+        dedupe: bool,
         /// Exact version number or use latest for latest release or master for latest build.
         version: String,
     },
     /// Clean everything except default and master, or give a specific version to clean just that.
     Clean {
+        #[arg(long, value_name = "N", conflicts_with = "version")]
+        /// Keep the newest N versions of every minor series, like 0.12.
+        keep_last: Option<usize>,
+        #[arg(long, value_parser = humantime::parse_duration, value_name = "DURATION", conflicts_with = "version")]
+        /// Only clean versions installed longer ago than this, like 90d.
+        older_than: Option<Duration>,
+        #[arg(long, value_parser = humantime::parse_duration, value_name = "DURATION", conflicts_with = "version")]
+        /// Only clean versions not run for this long, like 30d.
+        unused_since: Option<Duration>,
+        #[arg(long, conflicts_with = "version")]
+        /// Only clean master and development builds.
+        only_master_builds: bool,
+        #[arg(long)]
+        /// Only print what would be removed and how much space would be freed.
+        dry_run: bool,
+        #[arg(short, long)]
+        /// Do not ask for confirmation.
+        yes: bool,
         /// Exact version name or use latest for latest release and master for latest build.
         version: Option<String>,
     },
-    /// List all installed versions.
+    /// List all installed versions with their size and usage.
     List,
-    /// Prevent a version from being cleaned by clean command. Can be reverted by running clean with the particular version.
+    /// List versions available from ziglang.org for this machine.
+    LsRemote,
+    /// Show details of a version, installed or not.
+    Info {
+        /// Exact version number or use latest for latest release or master for latest build.
+        version: String,
+    },
+    /// Show disk space used by installed versions, the tarball cache and Zig's global cache.
+    Du,
+    /// Show or change settings in the config file.
+    Config {
+        #[command(subcommand)]
+        cmd: ConfigCmd,
+    },
+    /// Manage drop-ins compiling for other targets.
+    Dropins {
+        #[command(subcommand)]
+        cmd: DropinsCmd,
+    },
+    /// Generate build system files using Zig as the C and C++ compiler.
+    Toolchain {
+        #[command(subcommand)]
+        cmd: ToolchainCmd,
+    },
+    /// Manage Zig's global cache.
+    Cache {
+        #[command(subcommand)]
+        cmd: CacheCmd,
+    },
+    /// Hardlink identical files across installed versions and report the space saved.
+    Dedupe {
+        #[arg(long)]
+        /// Use reflinks instead of hardlinks where the filesystem supports them.
+        reflink: bool,
+    },
+    /// Compare installed files against the manifest recorded when they were installed.
+    Verify {
+        #[arg(long)]
+        /// Install changed versions again from the cached tarball or ziglang.org.
+        repair: bool,
+        /// Version or range like 0.12 to verify, every installed version otherwise.
+        version: Option<String>,
+    },
+    /// Check links, PATH, installs and leftover files for problems.
+    Doctor {
+        #[arg(long)]
+        /// Repair what can be repaired, which may download versions again.
+        fix: bool,
+    },
+    /// Make a writable copy of a version's standard library to debug it without changing the
+    /// install.
+    EditStd {
+        #[arg(long)]
+        /// Directory to create, zig-lib-<VERSION> in the current directory by default.
+        dir: Option<PathBuf>,
+        /// Installed version or range like 0.12, or pinned for the one in .zig-version.
+        version: String,
+    },
+    /// Prevent a version from being cleaned by clean command. Can be reverted with unkeep.
     Keep {
+        #[arg(long)]
+        /// Note on why the version is kept.
+        reason: Option<String>,
+        #[arg(long, value_parser = state::parse_until, value_name = "DATE")]
+        /// Date like 2026-12-31 or duration like 90d after which the version can be cleaned again.
+        until: Option<SystemTime>,
+        /// Exact version name or use latest for latest release and master for latest build.
+        version: String,
+    },
+    /// Allow a version saved with keep to be cleaned again, without removing it.
+    Unkeep {
         /// Exact version name or use latest for latest release and master for latest build.
         version: String,
     },
     /// Run a specific version of Zig with the given arguments.
     Run {
-        /// Exact version name or use latest for latest release and master for latest build.
+        #[arg(long)]
+        /// Install the version first if it is missing.
+        auto_install: bool,
+        /// Version or range like 0.12, latest, master, or pinned for the one in .zig-version.
         version: String,
         #[arg(
             trailing_var_arg = true, allow_hyphen_values = true, value_hint = ValueHint::CommandWithArguments
@@ -90,20 +204,135 @@ enum Cmd {
     },
 }
 
+impl Cmd {
+    /// Whether the command changes the install directory, and so must not run alongside another
+    /// that does.
+    fn mutates(&self) -> bool {
+        match self {
+            Cmd::Default { .. }
+            | Cmd::Fetch { .. }
+            | Cmd::Toolchain { .. }
+            | Cmd::Dedupe { .. }
+            | Cmd::Keep { .. }
+            | Cmd::Unkeep { .. } => true,
+            Cmd::Clean { dry_run, .. } => !dry_run,
+            Cmd::Dropins { cmd } => !matches!(cmd, DropinsCmd::List),
+            Cmd::Verify { repair, .. } => *repair,
+            Cmd::Doctor { fix } => *fix,
+            Cmd::Config {
+                cmd: ConfigCmd::Set { key, .. } | ConfigCmd::Unset { key },
+            } => key == "versioned_links" || key == "read_only",
+            _ => false,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum DropinsCmd {
+    /// Link drop-ins like aarch64-linux-musl-cc, -c++, -ar and -ranlib compiling for a target.
+    Add {
+        #[arg(long)]
+        /// Zig target triple, like aarch64-linux-musl.
+        target: String,
+        #[arg(long)]
+        /// CPU to compile for, passed as -mcpu.
+        cpu: Option<String>,
+        #[arg(long, allow_hyphen_values = true, value_delimiter = ' ')]
+        /// Extra flags for cc and c++, separated by spaces.
+        flags: Vec<String>,
+    },
+    /// List target drop-ins.
+    List,
+    /// Unlink the drop-ins for a target.
+    Remove {
+        /// Zig target triple given to add.
+        target: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ToolchainCmd {
+    /// Write a CMake toolchain file compiling with zig cc for a target.
+    Cmake {
+        #[arg(long)]
+        /// Zig target triple, like x86_64-linux-gnu.2.17.
+        target: String,
+        #[arg(long)]
+        /// CPU to compile for, passed as -mcpu.
+        cpu: Option<String>,
+    },
+    /// Write a Meson cross file compiling with zig cc for a target.
+    Meson {
+        #[arg(long)]
+        /// Zig target triple, like aarch64-linux-musl.
+        target: String,
+        #[arg(long)]
+        /// CPU to compile for, passed as -mcpu.
+        cpu: Option<String>,
+    },
+    /// Print or write the .cargo/config.toml section linking a Rust target with zig cc.
+    Cargo {
+        #[arg(long)]
+        /// Rust target triple, like aarch64-unknown-linux-musl.
+        target: String,
+        #[arg(long)]
+        /// Write the section to .cargo/config.toml next to .zig-version, or in the current directory.
+        write: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheCmd {
+    /// Remove old entries from Zig's global cache, which is shared by every version.
+    Prune {
+        #[arg(long, value_parser = humantime::parse_duration, value_name = "DURATION")]
+        /// Remove entries not used for this long, like 30d.
+        older_than: Option<Duration>,
+        #[arg(long, value_parser = parse_size, value_name = "SIZE")]
+        /// Remove the least recently used entries until the cache fits in this size, like 5GiB.
+        max_size: Option<u64>,
+        #[arg(long)]
+        /// Only print what would be removed.
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCmd {
+    /// Print the value of a setting.
+    Get {
+        /// One of install, link, dropins, mirrors, cache_limit, dedupe, reflink, auto_install,
+        /// versioned_links, read_only, prune_older_than or prune_max_size.
+        key: String,
+    },
+    /// Change a setting. Lists like mirrors are separated by commas.
+    Set {
+        /// One of install, link, dropins, mirrors, cache_limit, dedupe, reflink, auto_install,
+        /// versioned_links, read_only, prune_older_than or prune_max_size.
+        key: String,
+        /// New value of the setting.
+        value: String,
+    },
+    /// Remove a setting so its default is used.
+    Unset {
+        /// One of install, link, dropins, mirrors, cache_limit, dedupe, reflink, auto_install,
+        /// versioned_links, read_only, prune_older_than or prune_max_size.
+        key: String,
+    },
+    /// Print every setting.
+    List,
+}
+
 #[test]
 fn it_cli() {
     use clap::CommandFactory;
     Cli::command().debug_assert()
 }
 
-#[test]
-fn it_parse_ziglang_api() {
-    // let x = parse_ziglang_api("0.10").unwrap();
-    // dbg!(x);
-}
-
 #[test]
 fn it_sudo() {
+    use directories::{BaseDirs, ProjectDirs};
+
     let x = ProjectDirs::from("com", "", "zman")
         .ok_or_eyre("Default project directory could not be selected")
         .unwrap();
@@ -123,311 +352,313 @@ fn it_sudo() {
     );
 }
 
-#[test]
-fn it_download() {
-    // let x = download_tarxz("https://ziglang.org/download/0.11.0/zig-linux-x86_64-0.11.0.tar.xz");
-    // dbg!(r);
-}
+/// A failure already described in the command's output, so only the exit status is left to set.
+#[derive(Debug)]
+struct Reported(String);
 
-#[test]
-fn it_sha256() {
-    check_sha256(
-        &PathBuf::from("/tmp/zig-linux-x86_64-0.11.0.tar.xz"),
-        "2d00e789fec4f71790a6e7bf83ff91d564943c5ee843c5fd966efc474b423047".to_string(),
-    )
-    .unwrap();
+impl std::fmt::Display for Reported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
-#[test]
-fn it_extract() {
-    // extract_tarxz(
-    //     &PathBuf::from("/tmp/zig-linux-x86_64-0.11.0.tar.xz"),
-    //     &PathBuf::from("/tmp/zig")
-    // )
-    // .unwrap();
-}
+impl std::error::Error for Reported {}
 
-#[test]
-fn it_symlink() {
-    make_symlink(
-        &PathBuf::from("/tmp/zig/0.11.0"),
-        &PathBuf::from("/tmp/bin"),
-        false,
-    )
-    .unwrap();
+fn confirm(prompt: &str) -> Result<bool> {
+    let t = Term::stdout();
+    // Reading from anything but a terminal would take no answer as cancelling
+    if !t.is_term() || !stdin().is_terminal() {
+        return Err(zman::Error::new(
+            ErrorKind::Invalid,
+            format!("{} Pass --yes to confirm without a terminal", prompt),
+        )
+        .into());
+    }
+    t.write_str(&format!("{} [y/N] ", prompt))?;
+    let answer = t.read_line()?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
 }
 
-async fn parse_ziglang_api(client: &Client, version: &str) -> Result<(String, String, String)> {
-    let _e = "API could not be parsed";
+fn print_json(value: &Value) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
 
-    let tarball = format!("/{}-{}/tarball", ARCH, OS);
-    let sha = format!("/{}-{}/shasum", ARCH, OS);
-    let _e_arch = || eyre!("Zig binary for {} target not available", tarball);
+fn time(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
 
-    let api = client
-        .get("https://ziglang.org/download/index.json")
-        .send()
-        .await
-        .wrap_err_with(|| "Cannot connect to ziglang.org API")?
-        .json::<Value>()
-        .await
-        .wrap_err_with(|| _e)?;
+fn keep_json(keep: &state::Keep) -> Value {
+    json!({
+        "reason": keep.reason,
+        "since": time(keep.since),
+        "until": keep.until.map(time),
+        "expired": keep.is_expired(),
+    })
+}
 
-    match version {
-        "master" => {
-            let specific_version = api
-                .pointer("/master/version")
-                .ok_or_eyre(_e)?
-                .as_str()
-                .ok_or_eyre(_e)?
-                .to_string();
-            let url = api
-                .pointer(&format!("/master{}", tarball))
-                .ok_or_else(_e_arch)?
-                .as_str()
-                .ok_or_eyre(_e)?
-                .to_string();
-            let shasum = api
-                .pointer(&format!("/master{}", sha))
-                .ok_or_else(_e_arch)?
-                .as_str()
-                .ok_or_eyre(_e)?
-                .to_string();
-            Ok((specific_version, url, shasum))
-        }
-        "latest" => {
-            let api_map = api.as_object().ok_or_eyre(_e)?;
-            let mut latest: Option<&Value> = None;
-            let mut latest_date: Option<&str> = None;
-            let mut latest_version: Option<&str> = None;
-            for (ver, val) in api_map {
-                if ver != "master" {
-                    match latest_date {
-                        None => {
-                            let date = val
-                                .pointer("/date")
-                                .ok_or_eyre(_e)?
-                                .as_str()
-                                .ok_or_eyre(_e)?;
-                            latest = Some(val);
-                            latest_date = Some(date);
-                            latest_version = Some(ver);
-                        }
-                        Some(x) => {
-                            let val_date = val
-                                .pointer("/date")
-                                .ok_or_eyre(_e)?
-                                .as_str()
-                                .ok_or_eyre(_e)?;
-                            if x < val_date {
-                                latest = Some(val);
-                                latest_date = Some(val_date);
-                                latest_version = Some(ver);
-                            }
-                        }
-                    }
-                }
-            }
-            let specific_version = latest_version
-                .ok_or_eyre("Latest version could not be found")?
-                .to_string();
-            let url = latest
-                .ok_or_eyre(_e)?
-                .pointer(&tarball)
-                .ok_or_else(_e_arch)?
-                .as_str()
-                .ok_or_eyre(_e)?
-                .to_string();
-            let shasum = latest
-                .ok_or_eyre(_e)?
-                .pointer(&sha)
-                .ok_or_else(_e_arch)?
-                .as_str()
-                .ok_or_eyre(_e)?
-                .to_string();
-            Ok((specific_version, url, shasum))
-        }
-        version => {
-            let api_map = api.as_object().ok_or_eyre(_e)?;
-            let version_required = VersionReq::parse(&format!("={}", version))?;
-            let latest_matching_version = api_map
-                .keys()
-                .filter_map(|x| Version::parse(x).ok())
-                .filter(|x| version_required.matches(x))
-                .max();
-            let specific_version = latest_matching_version
-                .ok_or_else(|| eyre!("Version {} could not be found", version))?
-                .to_string();
-            let url = api
-                .pointer(&format!("/{}{}", specific_version, tarball))
-                .ok_or_else(_e_arch)?
-                .as_str()
-                .ok_or_eyre(_e)?
-                .to_string();
-            let shasum = api
-                .pointer(&format!("/{}{}", specific_version, sha))
-                .ok_or_else(_e_arch)?
-                .as_str()
-                .ok_or_eyre(_e)?
-                .to_string();
-            Ok((specific_version, url, shasum))
-        }
-    }
+/// The documented exit status for an error, from the kind of the first zman error in its chain.
+fn exit_code(e: &eyre::Report) -> (u8, Option<ErrorKind>) {
+    let kind = e
+        .chain()
+        .find_map(|x| x.downcast_ref::<zman::Error>())
+        .map(|x| x.kind());
+    let code = match kind {
+        Some(ErrorKind::NotFound) => 3,
+        Some(ErrorKind::Network) => 4,
+        Some(ErrorKind::Checksum) => 5,
+        Some(ErrorKind::Permission) => 6,
+        Some(ErrorKind::Interrupted) => 128 + interrupt::received().unwrap_or(2) as u8,
+        _ => 1,
+    };
+    (code, kind)
 }
 
-fn add_dropins(destination: &Path, dropins: [&str; 8], zig_location: PathBuf) -> Result<()> {
-    for x in dropins {
-        let file = format!("#!/bin/bash\nexec {:?} {} \"$@\"", zig_location, x);
-        let path = destination.join("zig-".to_string() + x);
-        write(&path, file)?;
-        set_permissions(&path, Permissions::from_mode(0o755))?;
+/// Runs Zig in place of zman, so it gets the terminal, signals and exit status to itself.
+fn exec(mut command: Command) -> Result<()> {
+    let zig = command.get_program().to_owned();
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        let e = command.exec();
+        Err(e).wrap_err_with(|| format!("Cannot run {:?}", zig))
+    }
+    #[cfg(not(unix))]
+    {
+        let status = command
+            .status()
+            .wrap_err_with(|| format!("Cannot run {:?}", zig))?;
+        std::process::exit(status.code().unwrap_or(1));
     }
-    Ok(())
 }
 
-fn rm_dropins(destination: &Path, dropins: [&str; 8]) -> Result<()> {
-    for x in dropins {
-        let path = destination.join("zig-".to_string() + x);
-        match remove_file(&path) {
-            Err(e) if e.kind() == ErrorKind::NotFound => {}
-            Err(e) => bail!(e),
-            Ok(_) => {}
-        };
+/// Drop-ins linked into the link directory of the shared install use it, so other accounts
+/// running them get its versions.
+fn launch_options(argv0: &Path) -> Options {
+    let dir = link::dropin_zig(argv0)
+        .ok()
+        .and_then(|x| x.parent().map(Path::to_path_buf));
+    Options {
+        system: dir.is_some_and(|x| x == zman::system_link_dir()),
+        ..Options::default()
     }
-    Ok(())
 }
 
-fn make_symlink(source: &Path, destination: &Path, no_dropins: bool) -> Result<()> {
-    let dropins = [
-        "ar", "cc", "c++", "dlltool", "lib", "ranlib", "objcopy", "rc",
-    ];
-    create_dir_all(destination)?;
-    match symlink(source.join("zig"), destination.join("zig")) {
-        Ok(_) => {
-            print!("Zig added at {:?}", destination);
-            if !no_dropins {
-                add_dropins(destination, dropins, source.join("zig"))?;
-                println!(" with drop-in tools");
-            }
-            if !var("PATH")?.contains(destination.to_str().ok_or_eyre("Path Invalid")?) {
-                println!("Add it to PATH");
+/// Runs what a drop-in like `zig-cc` or `aarch64-linux-musl-cc` stands for, with the version
+/// in its name like `zig-0.12-cc`, pinned by `.zig-version` or otherwise the `zig` linked next to
+/// the drop-in.
+fn launch(argv0: &Path) -> Result<()> {
+    let options = launch_options(argv0);
+    let mut zman = None;
+    let args = match link::dropin_subcommand(argv0) {
+        Some(x) => vec![x],
+        None => {
+            let name = argv0.file_name().unwrap_or_default().to_string_lossy();
+            let state = zman.insert(Zman::new(&options)?).state()?;
+            match state.targets.iter().find_map(|x| x.args(&name)) {
+                Some(x) => x,
+                None => {
+                    return Err(zman::Error::new(
+                        ErrorKind::NotFound,
+                        format!("No drop-in named {}. Add it with `zman dropins add`", name),
+                    )
+                    .into())
+                }
+            }
+        }
+    };
+    let series = link::dropin_series(argv0);
+    let pinned = match series {
+        // The version in a drop-in's name comes before any pin
+        Some(_) => None,
+        None => pin::find()?,
+    };
+    let zig = match (series, pinned) {
+        (Some(series), _) => {
+            let zman = match zman {
+                Some(x) => x,
+                None => Zman::new(&options)?,
             };
+            match zman.find(&series)? {
+                Some(x) => x.zig,
+                None => {
+                    return Err(zman::Error::new(
+                        ErrorKind::NotFound,
+                        format!(
+                            "Zig {} is not installed. Install it with `zman fetch {}`",
+                            series, series
+                        ),
+                    )
+                    .into())
+                }
+            }
         }
-        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
-            bail!("Permission denied to create symlink at {:?}. Do NOT RUN as root. Try passing a custom symlink directory with --link option", destination)
+        (None, Some((file, spec))) => {
+            let zman = match zman {
+                Some(x) => x,
+                None => Zman::new(&options)?,
+            };
+            match zman.find(&spec)? {
+                Some(x) => x.zig,
+                None => {
+                    return Err(zman::Error::new(
+                        ErrorKind::NotFound,
+                        format!(
+                        "Zig {} pinned by {:?} is not installed. Install it with `zman fetch {}`",
+                        spec, file, spec
+                    ),
+                    )
+                    .into())
+                }
+            }
         }
-        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
-            remove_file(destination.join("zig"))?;
-            rm_dropins(destination, dropins)?;
-            make_symlink(source, destination, no_dropins)?;
+        (None, None) => {
+            let zig = link::dropin_zig(argv0)?;
+            if zig.exists() {
+                zig
+            } else {
+                // Linked somewhere zig is not, like a linker drop-in for Cargo
+                let zman = match zman {
+                    Some(x) => x,
+                    None => Zman::new(&options)?,
+                };
+                let state = zman.state()?;
+                let Some(name) = state.default_install() else {
+                    bail!("No zig next to {:?} and no default version", argv0)
+                };
+                zman.toolchain(name, &state).zig
+            }
         }
-        Err(e) => bail!(e),
     };
-    Ok(())
+    let mut command = Command::new(zig);
+    command.args(args).args(args_os().skip(1));
+    exec(command)
 }
 
-fn check_sha256(file: &PathBuf, hash: String) -> Result<()> {
-    let mut file = File::open(file)?;
-    let mut hasher = Sha256::new();
-    let mut buffer = [0; 4096];
-    loop {
-        let bytes_read = file.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
-        }
-        hasher.update(&buffer[..bytes_read]);
-    }
-    let x = format!("{:x}", hasher.finalize());
-    ensure!(hash == x);
-    Ok(())
+fn add_cross_file(
+    zman: &Zman,
+    system: BuildSystem,
+    target: String,
+    cpu: Option<String>,
+) -> Result<PathBuf> {
+    cross::parse_target(&target)?;
+    Ok(zman.add_cross_file(CrossFile { system, target }, cpu)?)
 }
 
-fn extract_and_copy(
-    file: &Path,
-    extract_location: PathBuf,
-    install_location: &PathBuf,
-) -> Result<()> {
-    let _e = || eyre!("Extracting {:?} failed", file);
-    let xz = XzDecoder::new(File::open(file).wrap_err_with(_e)?);
-    let mut tar = Archive::new(xz);
-    let t = Term::stdout();
-    t.write_line("Extracting Zig...")?;
-    tar.unpack(&extract_location)?;
-    t.clear_line()?;
-    t.write_line("Installing Zig...")?;
-    create_dir_all(install_location)?;
-    let mut opts = CopyOptions::new();
-    opts.overwrite = true;
-    opts.content_only = true;
-    let inside_dir = read_dir(extract_location)?
-        .next()
-        .ok_or_eyre("Extracted directory not found")??;
-    create_dir_all(install_location)?;
-    copy(inside_dir.path(), install_location, &opts)?;
-    t.clear_line()?;
-    Ok(())
-}
-
-fn main() -> Result<()> {
+fn main() -> ExitCode {
+    let argv0 = PathBuf::from(args_os().next().unwrap_or_default());
+    if link::dropin_subcommand(&argv0).is_some() || link::is_target_dropin(&argv0) {
+        return match launch(&argv0) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                ExitCode::from(exit_code(&e).0)
+            }
+        };
+    }
     let cli = Cli::parse();
+    let json = cli.json;
+    interrupt::handle_signals();
+    // --json keeps stdout for its document, so only JSON events can be shown alongside it
+    let progress = match cli.progress {
+        ProgressMode::Json => Progress::Json,
+        _ if json => Progress::Hidden,
+        ProgressMode::Auto if stderr().is_terminal() => Progress::Bar,
+        ProgressMode::Auto | ProgressMode::Plain => Progress::Plain,
+        ProgressMode::Bar => Progress::Bar,
+        ProgressMode::None => Progress::Hidden,
+    };
+    // A signal deferred by a guard after the work last checked for it still fails the command, so
+    // scripts can tell it was interrupted
+    let result = run(cli, progress).and_then(|()| Ok(interrupt::check()?));
+    match result {
+        Ok(()) => {
+            progress.emit(&Event::Done);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            let (code, kind) = exit_code(&e);
+            progress.emit(&Event::Error {
+                kind,
+                message: e.to_string(),
+            });
+            if json && e.is::<Reported>() {
+                // The document was printed already
+            } else if json {
+                let _ = print_json(&json!({
+                    "error": {
+                        "kind": kind,
+                        "message": e.to_string(),
+                        "causes": e.chain().skip(1).map(|x| x.to_string()).collect::<Vec<_>>(),
+                    }
+                }));
+            } else {
+                eprintln!("Error: {:?}", e);
+            }
+            ExitCode::from(code)
+        }
+    }
+}
 
-    let x = ProjectDirs::from("com", "", "zman")
-        .ok_or_eyre("Default project directory could not be selected")?;
-    let install_default = x.data_dir();
-    let x = BaseDirs::new().ok_or_eyre("User home directory could not be found")?;
-    let link_default = x
-        .executable_dir()
-        .ok_or_eyre("Local bin directory could not be found")?;
+fn run(cli: Cli, progress: Progress) -> Result<()> {
+    let json = cli.json;
+    let mut zman = Zman::new(&Options {
+        install: cli.install,
+        progress,
+        system: cli.system,
+        repair_config: matches!(cli.cmd, Cmd::Config { .. } | Cmd::Doctor { .. }),
+    })?;
+    // Files zman creates in a shared install, like the state file, stay writable by the group
+    let umask = zman.system.then(|| {
+        // SAFETY: umask cannot fail
+        unsafe { libc::umask(0o002) }
+    });
+    let _lock = match cli.cmd.mutates() {
+        true => Some(zman.lock()?),
+        false => None,
+    };
 
     match cli.cmd {
         Cmd::Default {
-            ref install,
             ref version,
+            dedupe,
             ..
         }
         | Cmd::Fetch {
-            ref install,
             ref version,
+            dedupe,
         } => {
             // link_location: ./local/bin/ -symlink-> version_link_location
             // install_location: ./local/share/zman/
             // version_install_location: ./local/share/zman/0.11.0/ or ./local/share/zman/master/
             // Not Implemented - version_link_location: ./local/share/zman/bin/
 
-            let install_location = match install {
-                Some(x) => x,
-                None => install_default,
-            };
-
-            let client = Client::new();
-            let rt = Runtime::new()?;
-
-            let (specific_version, url, shasum) =
-                rt.block_on(parse_ziglang_api(&client, version))?;
-            let specific_install_location = if "master" == version {
-                install_location.join("master")
-            } else {
-                install_location.join(&specific_version)
-            };
-            if version != "master"
-                && specific_install_location
-                    .join("zig")
-                    .try_exists()
-                    .wrap_err_with(|| {
-                        eyre!("Cannot check if {:?} already downloaded", specific_version)
-                    })?
-            {
-                println!("Zig version {} already downloaded", specific_version);
-            } else {
-                let temp = TempDir::with_prefix("zman")?;
-                let extract_location = temp.child(version);
-                let tarxz = temp.child(format!("zig-{}.tar.xz", version));
-                rt.block_on(download_file(&client, &url, &tarxz))
-                    .wrap_err_with(|| eyre!("Downloading {:?} failed", specific_version))?;
-                check_sha256(&tarxz, shasum)
-                    .wrap_err_with(|| eyre!("Checksum failed for {:?}", specific_version))?;
-                extract_and_copy(&tarxz, extract_location, &specific_install_location)?;
+            zman.config.dedupe |= dedupe;
+            let user = matches!(cli.cmd, Cmd::Default { user: true, .. });
+            ensure!(
+                !user || zman.system,
+                "--user only applies to the shared install, use it with --system"
+            );
+            if user && version == "system" {
+                let reset = zman.reset_user_default()?;
+                if json {
+                    print_json(&json!({ "reset": reset }))?;
+                } else if reset {
+                    println!("Using the system default version again");
+                } else {
+                    println!("No default version of your own was set");
+                }
+                return Ok(());
             }
+            let release = zman.resolve(version)?;
+            let downloaded = zman.installed(&release, version)?.is_none();
+            let toolchain = zman.install(&release, version)?;
+            let mut output = json!({
+                "name": toolchain.name,
+                "version": release.version,
+                "path": toolchain.root,
+                "downloaded": downloaded,
+            });
             if let Cmd::Default {
                 ref link,
                 no_dropins,
@@ -435,82 +666,746 @@ fn main() -> Result<()> {
             } = cli.cmd
             {
                 let link_location = match link {
-                    Some(x) => x,
-                    None => link_default,
+                    Some(x) => x.clone(),
+                    None if user => zman::user_link_dir()?,
+                    None => zman.link.clone(),
                 };
-                make_symlink(&specific_install_location, link_location, no_dropins)?;
-                write(install_default.join("default.txt"), version)?;
+                let link_location = &link_location;
+                let dropins = if no_dropins {
+                    Vec::new()
+                } else {
+                    zman.config.dropins()
+                };
+                if user {
+                    zman.set_user_default(version, &toolchain, link_location, &dropins)?;
+                } else {
+                    zman.set_default(version, &toolchain, link_location, &dropins)?;
+                }
+                let in_path =
+                    var("PATH")?.contains(link_location.to_str().ok_or_eyre("Path Invalid")?);
+                output["link"] = json!(link_location);
+                output["dropins"] = json!(dropins);
+                output["in_path"] = json!(in_path);
+                if !json {
+                    if dropins.is_empty() {
+                        println!("Zig added at {:?}", link_location);
+                    } else {
+                        println!("Zig added at {:?} with drop-in tools", link_location);
+                    }
+                    if !in_path {
+                        println!("Add it to PATH");
+                    };
+                }
+            }
+            if json {
+                print_json(&output)?;
             }
         }
-        Cmd::Clean { version } => {
-            let default = read_to_string(install_default.join("default.txt"))?;
-            let k = read_to_string(install_default.join("keep.txt"))?;
-            let keeps: Vec<&str> = k.split("\n").collect();
-            match version {
-                Some(v) if v == default => {
+        Cmd::Clean {
+            keep_last,
+            older_than,
+            unused_since,
+            only_master_builds,
+            dry_run,
+            yes,
+            version,
+        } => {
+            let mut state = zman.state()?;
+            let default = state.default.as_ref();
+            // A version named on the command line is removed without asking
+            let ask = !yes && version.is_none();
+            let selected = match version {
+                Some(v) if default.is_some_and(|x| x.install == v || x.spec == v) => {
                     bail!("Cannot remove default version. Set some other version as default and try again")
                 }
+                Some(v) if state.user_defaults.iter().any(|x| x.install == v) => {
+                    let users = state
+                        .user_defaults
+                        .iter()
+                        .filter(|x| x.install == v)
+                        .map(|x| x.user.clone().unwrap_or_else(|| x.uid.to_string()))
+                        .collect::<Vec<_>>();
+                    bail!(
+                        "Cannot remove {}, the default version of {}",
+                        v,
+                        users.join(", ")
+                    )
+                }
                 Some(v) => {
-                    remove_dir_all(install_default.join(&v))?;
-                    println!("{:?} removed", install_default.join(v));
+                    let Some(install) = installs(&zman.install)?.into_iter().find(|x| x.name == v)
+                    else {
+                        return Err(zman::Error::new(
+                            ErrorKind::NotFound,
+                            format!("Version {} is not installed", v),
+                        )
+                        .into());
+                    };
+                    vec![install]
                 }
                 None => {
-                    let list = read_dir(install_default)?;
-                    for f in list {
-                        let folder = f?;
-                        let filename = folder.file_name();
-                        if filename != "master"
-                            && filename != default.as_str()
-                            && filename != "default.txt"
-                            && filename != "keep.txt"
-                            && !keeps.contains(
-                                &filename
-                                    .to_str()
-                                    .ok_or_else(|| eyre!("Cannot convert filenames to string"))?,
-                            )
-                        {
-                            remove_dir_all(folder.path())?;
-                            println!("{:?} removed", folder.path());
+                    let policy = clean::Policy {
+                        keep_last,
+                        older_than,
+                        unused_since,
+                        only_master_builds,
+                    };
+                    let mut protected: Vec<&str> = state
+                        .keep
+                        .iter()
+                        .filter(|x| !x.is_expired())
+                        .map(|x| x.version.as_str())
+                        .collect();
+                    protected.extend(state.default_install());
+                    protected.extend(state.user_defaults.iter().map(|x| x.install.as_str()));
+                    clean::select(installs(&zman.install)?, &protected, &policy)
+                }
+            };
+            let mut sizes = Vec::new();
+            for install in &selected {
+                sizes.push(install.size()?);
+            }
+            let freed: u64 = sizes.iter().sum();
+            let output = json!({
+                "dry_run": dry_run,
+                "removed": selected
+                    .iter()
+                    .zip(&sizes)
+                    .map(|(x, size)| json!({ "name": x.name, "path": x.path, "size": size }))
+                    .collect::<Vec<_>>(),
+                "freed": freed,
+            });
+            if !json {
+                if selected.is_empty() {
+                    println!("Nothing to clean");
+                    return Ok(());
+                }
+                for (install, size) in selected.iter().zip(&sizes) {
+                    println!("{:?} ({})", install.path, HumanBytes(*size));
+                }
+            }
+            if dry_run || selected.is_empty() {
+                if json {
+                    print_json(&output)?;
+                } else {
+                    println!(
+                        "{} versions would be removed, freeing {}",
+                        selected.len(),
+                        HumanBytes(freed)
+                    );
+                }
+                return Ok(());
+            }
+            if ask {
+                ensure!(!json, "Pass --yes to clean with --json");
+                if !confirm(&format!(
+                    "Remove {} versions, freeing {}?",
+                    selected.len(),
+                    HumanBytes(freed)
+                ))? {
+                    return Ok(());
+                }
+            }
+            for install in selected {
+                zman.remove(&install.name, &mut state)?;
+                if !json {
+                    println!("{:?} removed", install.path);
+                }
+            }
+            if json {
+                print_json(&output)?;
+            }
+        }
+        Cmd::List => {
+            let state = zman.state()?;
+            let pinned = pin::find()?.map(|(_, x)| x);
+            let mut output = Vec::new();
+            for install in installs(&zman.install)? {
+                let resolved = match state.installs.get(&install.name) {
+                    Some(x) => Some(x.version.clone()),
+                    None => install.resolved(),
+                };
+                let size = install.size()?;
+                let default = state.default_install() == Some(&install.name);
+                let user_default = state
+                    .user_default(zman::current_uid())
+                    .is_some_and(|x| x.install == install.name);
+                let kept = state.kept(&install.name);
+                let is_pinned = pinned.as_ref() == Some(&install.name);
+                if json {
+                    output.push(json!({
+                        "name": install.name,
+                        "version": resolved,
+                        "path": install.path,
+                        "installed": time(install.installed),
+                        "last_used": install.last_used.map(time),
+                        "size": size,
+                        "default": default,
+                        "user_default": user_default,
+                        "keep": kept.map(keep_json),
+                        "pinned": is_pinned,
+                    }));
+                    continue;
+                }
+                let mut line = install.name.clone();
+                if let Some(resolved) = resolved.filter(|x| *x != install.name) {
+                    line += &format!(" ({})", resolved);
+                }
+                line += &format!(
+                    "  installed {}  {}",
+                    installs::date(install.installed),
+                    HumanBytes(size)
+                );
+                if let Some(last_used) = install.last_used {
+                    line += &format!("  used {}", installs::ago(last_used));
+                }
+                let mut markers = Vec::new();
+                if default {
+                    markers.push("default");
+                }
+                if user_default {
+                    markers.push("your default");
+                }
+                let keep = kept.map(|x| {
+                    let mut keep = "keep".to_string();
+                    if let Some(until) = x.until {
+                        let expiry = if x.is_expired() { "expired" } else { "until" };
+                        keep += &format!(" {} {}", expiry, installs::date(until));
+                    }
+                    if let Some(reason) = &x.reason {
+                        keep += &format!(": {}", reason);
+                    }
+                    keep
+                });
+                if let Some(keep) = &keep {
+                    markers.push(keep);
+                }
+                if is_pinned {
+                    markers.push("pinned");
+                }
+                if !markers.is_empty() {
+                    line += &format!("  [{}]", markers.join(", "));
+                }
+                println!("{}", line);
+            }
+            if json {
+                print_json(&json!(output))?;
+            }
+        }
+        Cmd::LsRemote => {
+            let state = zman.state()?;
+            let available = index::available(&zman.index()?)?;
+            let installed = |x: &index::Available| match state.installs.get(&x.name) {
+                Some(record) => record.version == x.version,
+                None => zman.install.join(&x.name).join("zig").exists(),
+            };
+            if json {
+                let output: Vec<Value> = available
+                    .iter()
+                    .map(|x| {
+                        json!({
+                            "name": x.name,
+                            "version": x.version,
+                            "date": x.date,
+                            "installed": installed(x),
+                        })
+                    })
+                    .collect();
+                print_json(&json!(output))?;
+            } else {
+                for x in &available {
+                    let mut line = x.name.clone();
+                    if x.version != x.name {
+                        line += &format!(" ({})", x.version);
+                    }
+                    if let Some(date) = &x.date {
+                        line += &format!("  {}", date);
+                    }
+                    if installed(x) {
+                        line += "  [installed]";
+                    }
+                    println!("{}", line);
+                }
+            }
+        }
+        Cmd::Info { version } => {
+            let state = zman.state()?;
+            let local = match version.as_str() {
+                "latest" => None,
+                x => installs(&zman.install)?.into_iter().find(|i| i.name == x),
+            };
+            let output = match local {
+                Some(install) => {
+                    let record = state.installs.get(&install.name);
+                    json!({
+                        "name": install.name,
+                        "version": record.map(|x| x.version.clone()).or_else(|| install.resolved()),
+                        "installed": true,
+                        "path": install.path,
+                        "installed_at": time(record.map_or(install.installed, |x| x.installed)),
+                        "last_used": install.last_used.map(time),
+                        "size": install.size()?,
+                        "shasum": record.and_then(|x| x.shasum.clone()),
+                        "default": state.default_install() == Some(&install.name),
+                        "keep": state.kept(&install.name).map(keep_json),
+                    })
+                }
+                None => {
+                    let release = zman.resolve(&version)?;
+                    let toolchain = zman.installed(&release, &version)?;
+                    json!({
+                        "name": release.install_name(&version),
+                        "version": release.version,
+                        "installed": toolchain.is_some(),
+                        "path": toolchain.map(|x| x.root),
+                        "url": release.url,
+                        "shasum": release.shasum,
+                    })
+                }
+            };
+            if json {
+                print_json(&output)?;
+            } else if let Some(fields) = output.as_object() {
+                let nested = fields.iter().flat_map(|(key, value)| match value {
+                    Value::Object(x) => x
+                        .iter()
+                        .map(|(sub, value)| (format!("{}.{}", key, sub), value))
+                        .collect(),
+                    x => vec![(key.clone(), x)],
+                });
+                for (key, value) in nested {
+                    match value {
+                        Value::Null => {}
+                        Value::String(x) => println!("{}: {}", key, x),
+                        Value::Number(x) if key == "size" => {
+                            println!("{}: {}", key, HumanBytes(x.as_u64().unwrap_or_default()))
                         }
+                        x => println!("{}: {}", key, x),
                     }
                 }
             }
         }
-        Cmd::List => {
-            let folders = read_dir(install_default)?;
-            for f in folders {
-                let filename = f?.file_name();
-                if filename != "default.txt" || filename != "keep.txt" {
-                    println!("{:?}", filename);
+        Cmd::Du => {
+            let mut seen = HashSet::new();
+            let installs = installs(&zman.install)?;
+            let count = installs.len();
+            // Only the versions themselves, as hidden directories hold zman's own files like the
+            // tarball cache of the shared install
+            let mut installed = 0;
+            for install in &installs {
+                installed += disk_usage(&install.path, &mut seen)?;
+            }
+            let tarballs = disk_usage(&zman.cache, &mut seen)?;
+            let zig_cache = global_cache::dir();
+            let global = match &zig_cache {
+                Some(x) => Some(disk_usage(x, &mut seen)?),
+                None => None,
+            };
+            let total = installed + tarballs + global.unwrap_or_default();
+            if json {
+                print_json(&json!({
+                    "installs": { "versions": count, "size": installed },
+                    "tarball_cache": { "size": tarballs },
+                    "global_cache": zig_cache.map(|x| json!({ "path": x, "size": global })),
+                    "total": total,
+                }))?;
+                return Ok(());
+            }
+            println!("Installs ({} versions): {}", count, HumanBytes(installed));
+            println!("Tarball cache: {}", HumanBytes(tarballs));
+            if let (Some(x), Some(size)) = (zig_cache, global) {
+                println!("Zig global cache {:?}: {}", x, HumanBytes(size));
+            }
+            println!("Total: {}", HumanBytes(total));
+        }
+        Cmd::Dropins { cmd } => match cmd {
+            DropinsCmd::Add { target, cpu, flags } => {
+                cross::parse_target(&target)?;
+                let target = TargetDropins { target, cpu, flags };
+                let names = target.names();
+                zman.add_target(target)?;
+                println!("Added {}", names.join(", "));
+            }
+            DropinsCmd::List => {
+                for target in zman.state()?.targets {
+                    let mut line = target.target.clone();
+                    if let Some(cpu) = &target.cpu {
+                        line += &format!("  cpu {}", cpu);
+                    }
+                    if !target.flags.is_empty() {
+                        line += &format!("  flags {}", target.flags.join(" "));
+                    }
+                    line += &format!("  [{}]", target.names().join(", "));
+                    println!("{}", line);
+                }
+            }
+            DropinsCmd::Remove { target } => zman.remove_target(&target)?,
+        },
+        Cmd::Toolchain {
+            cmd: ToolchainCmd::Cargo { target, write },
+        } => {
+            // The drop-ins run the pinned version inside the project, so it has to be installed
+            let pinned = pin::find()?;
+            if let Some((file, spec)) = &pinned {
+                if zman.find(spec)?.is_none() {
+                    return Err(zman::Error::new(
+                        ErrorKind::NotFound,
+                        format!(
+                            "Zig {} pinned by {:?} is not installed. Install it with `zman fetch {}`",
+                            spec, file, spec
+                        ),
+                    )
+                    .into());
+                }
+            }
+            let (linker, ar) = zman.cargo_linker(&target)?;
+            if write {
+                let project = match &pinned {
+                    Some((file, _)) => file.parent().map(Path::to_path_buf).unwrap_or_default(),
+                    None => current_dir()?,
                 };
+                let file = project.join(".cargo").join("config.toml");
+                cross::write_cargo_config(&file, &target, &linker, &ar)?;
+                println!("Linker for {} written to {:?}", target, file);
+            } else {
+                println!("[target.{}]", target);
+                println!("linker = {:?}", linker);
+                println!("ar = {:?}", ar);
             }
-            let default = read_to_string(install_default.join("default.txt"))?;
-            println!("Default version: {}", default);
-            let keeps = read_to_string(install_default.join("keep.txt"))?
-                .split("\n")
-                .collect::<Vec<&str>>()
-                .join(", ");
-            println!("Saved from cleaning: {}", keeps);
         }
-        Cmd::Keep { version } => match read_to_string(install_default.join("keep.txt")) {
-            Ok(v) => write(
-                install_default.join("keep.txt"),
-                format!("{}\n{}", v, version),
-            )?,
-            Err(e) if e.kind() == ErrorKind::NotFound => {
-                write(install_default.join("keep.txt"), version)?
-            }
-            Err(e) => bail!(e),
+        Cmd::Toolchain {
+            cmd: ToolchainCmd::Cmake { target, cpu },
+        } => {
+            let file = add_cross_file(&zman, BuildSystem::Cmake, target, cpu)?;
+            println!("cmake -DCMAKE_TOOLCHAIN_FILE={:?}", file);
+        }
+        Cmd::Toolchain {
+            cmd: ToolchainCmd::Meson { target, cpu },
+        } => {
+            let file = add_cross_file(&zman, BuildSystem::Meson, target, cpu)?;
+            println!("meson setup --cross-file {:?} build", file);
+        }
+        Cmd::Cache {
+            cmd:
+                CacheCmd::Prune {
+                    older_than,
+                    max_size,
+                    dry_run,
+                },
+        } => {
+            let (older_than, max_size) = match (older_than, max_size) {
+                (None, None) => (
+                    zman.config.prune_older_than()?,
+                    zman.config.prune_max_size()?,
+                ),
+                x => x,
+            };
+            ensure!(
+                older_than.is_some() || max_size.is_some(),
+                "Pass --older-than or --max-size, or set prune_older_than or prune_max_size, to \
+                 choose what to prune"
+            );
+            let zig_cache =
+                global_cache::dir().ok_or_eyre("Zig global cache directory could not be found")?;
+            let selected =
+                global_cache::select(global_cache::entries(&zig_cache)?, older_than, max_size);
+            let freed = HumanBytes(selected.iter().map(|x| x.size).sum());
+            if dry_run {
+                for entry in &selected {
+                    println!("{:?} would be removed", entry.path);
+                }
+                println!("{} would be freed", freed);
+            } else {
+                let count = selected.len();
+                let skipped = global_cache::remove(&zig_cache, selected)?;
+                for entry in &skipped {
+                    eprintln!("{:?} is in use by a running zig, skipped", entry.path);
+                }
+                let freed = freed.0 - skipped.iter().map(|x| x.size).sum::<u64>();
+                println!(
+                    "{} entries removed, {} freed",
+                    count - skipped.len(),
+                    HumanBytes(freed)
+                );
+            }
+        }
+        Cmd::Config { cmd } => match cmd {
+            ConfigCmd::Get { key } => match config::get(&zman.config_file, &key)? {
+                Some(x) => println!("{}", x),
+                None => println!("{} is not set", key),
+            },
+            ConfigCmd::Set { ref key, .. } | ConfigCmd::Unset { ref key } => {
+                let value = match &cmd {
+                    ConfigCmd::Set { value, .. } => Some(value.as_str()),
+                    _ => None,
+                };
+                config::set(&zman.config_file, key, value)?;
+                if key == "versioned_links" {
+                    zman.config = config::Config::load(&zman.config_file)?;
+                    zman.link_versions(&mut zman.state()?)?;
+                }
+                if key == "read_only" {
+                    zman.config = config::Config::load(&zman.config_file)?;
+                    zman.protect_installs()?;
+                }
+            }
+            ConfigCmd::List => {
+                println!("# {:?}", zman.config_file);
+                for (key, _) in config::KEYS {
+                    match config::get(&zman.config_file, key)? {
+                        Some(x) => println!("{} = {}", key, x),
+                        None => println!("{} is not set", key),
+                    }
+                }
+            }
         },
-        Cmd::Run { version, args } => {
-            let output = Command::new(install_default.join(version).join("zig"))
-                .args(args)
-                .output()?;
-            if output.status.success() {
-                println!("{}", String::from_utf8_lossy(&output.stdout));
+        Cmd::Verify { repair, version } => {
+            let mut names: Vec<String> = installs(&zman.install)?
+                .into_iter()
+                .map(|x| x.name)
+                .collect();
+            if let Some(v) = version {
+                let name = match names.iter().find(|x| **x == v) {
+                    Some(x) => x.clone(),
+                    None => match zman.find(&v)? {
+                        Some(x) => x.name,
+                        None => {
+                            return Err(zman::Error::new(
+                                ErrorKind::NotFound,
+                                format!("Version {} is not installed", v),
+                            )
+                            .into())
+                        }
+                    },
+                };
+                names = vec![name];
+            }
+            let mut output = Vec::new();
+            let mut changed = 0;
+            for name in names {
+                let root = zman.install.join(&name);
+                let changes = match Manifest::load(&zman.install, &name)? {
+                    Some(x) => Some(x.verify(&root)?),
+                    None => None,
+                };
+                let ok = changes.as_ref().is_some_and(|x| x.is_empty());
+                let repaired = repair && !ok;
+                if !json {
+                    match &changes {
+                        Some(x) if x.is_empty() => println!("{}: ok", name),
+                        Some(x) => {
+                            println!(
+                                "{}: {} modified, {} missing, {} extra",
+                                name,
+                                x.modified.len(),
+                                x.missing.len(),
+                                x.extra.len()
+                            );
+                            for (kind, files) in [
+                                ("modified", &x.modified),
+                                ("missing", &x.missing),
+                                ("extra", &x.extra),
+                            ] {
+                                for file in files {
+                                    println!("  {:<8} {}", kind, file);
+                                }
+                            }
+                        }
+                        None => println!(
+                            "{}: no manifest, installed by an older zman. Repair to record one",
+                            name
+                        ),
+                    }
+                }
+                if repaired {
+                    zman.repair(&name)?;
+                    if !json {
+                        println!("{} repaired", name);
+                    }
+                } else if changes.as_ref().is_some_and(|x| !x.is_empty()) {
+                    changed += 1;
+                }
+                let mut entry = json!({
+                    "name": name,
+                    "status": match &changes {
+                        Some(x) if x.is_empty() => "ok",
+                        Some(_) => "changed",
+                        None => "unknown",
+                    },
+                    "repaired": repaired,
+                });
+                if let Some(x) = &changes {
+                    entry["modified"] = json!(x.modified);
+                    entry["missing"] = json!(x.missing);
+                    entry["extra"] = json!(x.extra);
+                }
+                output.push(entry);
+            }
+            if json {
+                print_json(&json!(output))?;
+            }
+            if changed > 0 {
+                return Err(Reported(format!(
+                    "{} versions changed. Repair them with --repair",
+                    changed
+                ))
+                .into());
+            }
+        }
+        Cmd::Doctor { fix } => {
+            let mut checks = doctor::check(&zman)?;
+            let mut fixed = Vec::new();
+            if fix {
+                // Fixing the state file can reveal problems it hid, so check again until nothing
+                // new can be fixed
+                loop {
+                    let pending: Vec<_> = checks
+                        .iter()
+                        .filter(|x| x.fixable && x.status != doctor::Status::Ok)
+                        .filter(|x| {
+                            !fixed
+                                .iter()
+                                .any(|(name, message, _)| *name == x.name && *message == x.message)
+                        })
+                        .collect();
+                    if pending.is_empty() {
+                        break;
+                    }
+                    for check in pending {
+                        let error = check.fix(&zman).err().map(|e| e.to_string());
+                        if !json {
+                            match &error {
+                                None => println!("Fixed {}: {}", check.name, check.message),
+                                Some(e) => println!("Cannot fix {}: {}", check.name, e),
+                            }
+                        }
+                        fixed.push((check.name.clone(), check.message.clone(), error));
+                    }
+                    checks = doctor::check(&zman)?;
+                }
+            }
+            let problems = checks
+                .iter()
+                .filter(|x| x.status == doctor::Status::Problem)
+                .count();
+            if json {
+                print_json(&json!({
+                    "checks": checks,
+                    "fixed": fixed
+                        .iter()
+                        .map(|(name, message, error)| {
+                            json!({ "name": name, "message": message, "error": error })
+                        })
+                        .collect::<Vec<_>>(),
+                    "healthy": problems == 0,
+                }))?;
             } else {
-                eprintln!("{}", String::from_utf8_lossy(&output.stderr));
+                for check in &checks {
+                    let status = match check.status {
+                        doctor::Status::Ok => "ok",
+                        doctor::Status::Warning => "warning",
+                        doctor::Status::Problem => "problem",
+                    };
+                    let hint = if check.fixable && check.status != doctor::Status::Ok {
+                        " (fixable with --fix)"
+                    } else {
+                        ""
+                    };
+                    println!("{:<8} {}: {}{}", status, check.name, check.message, hint);
+                }
+            }
+            if problems > 0 {
+                return Err(Reported(format!("{} problems found", problems)).into());
+            }
+        }
+        Cmd::Dedupe { reflink } => {
+            println!("{}", zman.dedupe(reflink || zman.config.reflink)?)
+        }
+        Cmd::Keep {
+            reason,
+            until,
+            version,
+        } => {
+            let mut state = zman.state()?;
+            state.keep(&version, reason, until);
+            state.save(&zman.install)?;
+        }
+        Cmd::EditStd { dir, version } => {
+            let spec = pin::expand(&version)?;
+            let Some(toolchain) = zman.find(&spec)? else {
+                return Err(zman::Error::new(
+                    ErrorKind::NotFound,
+                    format!("Version {} is not installed", spec),
+                )
+                .into());
+            };
+            let dir = match dir {
+                Some(x) => x,
+                None => current_dir()?.join(format!("zig-lib-{}", toolchain.name)),
+            };
+            zman::install::overlay_std(&toolchain.lib, &dir)?;
+            if json {
+                print_json(&json!({
+                    "name": toolchain.name,
+                    "dir": dir,
+                    "std": dir.join("std"),
+                }))?;
+            } else {
+                println!(
+                    "Writable std of {} copied to {:?}",
+                    toolchain.name,
+                    dir.join("std")
+                );
+                println!(
+                    "Build with it using `zig build --zig-lib-dir {}`",
+                    dir.display()
+                );
+            }
+        }
+        Cmd::Unkeep { version } => {
+            let mut state = zman.state()?;
+            if !state.unkeep(&version) {
+                bail!("Version {} is not kept", version)
+            }
+            state.save(&zman.install)?;
+        }
+        Cmd::Run {
+            auto_install,
+            version,
+            args,
+        } => {
+            let spec = pin::expand(&version)?;
+            let toolchain = match zman.find(&spec)? {
+                Some(x) => x,
+                None => {
+                    let release = zman.resolve(&spec)?;
+                    match zman.installed(&release, &spec)? {
+                        Some(x) => x,
+                        None if auto_install || zman.config.auto_install => {
+                            let _lock = zman.lock()?;
+                            zman.install(&release, &spec)?
+                        }
+                        None => {
+                            return Err(zman::Error::new(
+                                ErrorKind::NotFound,
+                                format!(
+                                    "Zig {} is not installed. Install it with `zman fetch {}` or pass --auto-install",
+                                    release.version,
+                                    release.install_name(&spec)
+                                ),
+                            )
+                            .into())
+                        }
+                    }
+                }
+            };
+            if let Some(umask) = umask {
+                // Zig gets the umask zman was started with
+                // SAFETY: umask cannot fail
+                unsafe { libc::umask(umask) };
             }
+            let mut command = Command::new(&toolchain.zig);
+            command.args(args);
+            exec(command)?;
         }
     }
 
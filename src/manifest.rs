@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::cache::sha256;
+use crate::error::{Context, Result};
+use crate::link::remove_if_exists;
+
+/// Directory in the install directory holding a manifest for every install, hidden so it is not
+/// taken for an install.
+pub const DIR: &str = ".manifests";
+
+/// Every file of an install as extracted, to tell whether it was changed since.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: String,
+    /// SHA-256 of the tarball the install was extracted from.
+    pub shasum: String,
+    /// Files by their path relative to the install, with `/` separators.
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct FileEntry {
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Files that differ from the manifest, by their path relative to the install.
+#[derive(Serialize, Default)]
+pub struct Changes {
+    pub modified: Vec<String>,
+    pub missing: Vec<String>,
+    pub extra: Vec<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.modified.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+fn path(install_location: &Path, name: &str) -> PathBuf {
+    install_location.join(DIR).join(format!("{}.json", name))
+}
+
+impl Manifest {
+    /// Hashes every file below `root`.
+    pub fn create(root: &Path, version: &str, shasum: &str) -> Result<Manifest> {
+        Ok(Manifest {
+            version: version.to_string(),
+            shasum: shasum.to_string(),
+            files: files(root)?
+                .into_iter()
+                .map(|(name, path)| {
+                    let entry = FileEntry {
+                        size: path.metadata()?.len(),
+                        sha256: sha256(&path)?,
+                    };
+                    Ok((name, entry))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// The manifest of the install `name`, which installs from older versions of zman lack.
+    pub fn load(install_location: &Path, name: &str) -> Result<Option<Manifest>> {
+        let file = path(install_location, name);
+        match read_to_string(&file) {
+            Ok(x) => serde_json::from_str(&x)
+                .map(Some)
+                .with_context(|| format!("Manifest {:?} could not be parsed", file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Cannot read manifest {:?}", file)),
+        }
+    }
+
+    pub fn save(&self, install_location: &Path, name: &str) -> Result<()> {
+        let file = path(install_location, name);
+        create_dir_all(install_location.join(DIR))?;
+        write(&file, serde_json::to_string(self)?)
+            .with_context(|| format!("Cannot write manifest {:?}", file))
+    }
+
+    pub fn remove(install_location: &Path, name: &str) -> Result<()> {
+        remove_if_exists(&path(install_location, name))
+    }
+
+    /// Compares the files below `root` against the manifest. Sizes are compared first so only
+    /// files of the right size are hashed.
+    pub fn verify(&self, root: &Path) -> Result<Changes> {
+        let mut changes = Changes::default();
+        let mut found = files(root)?;
+        for (name, entry) in &self.files {
+            match found.remove(name) {
+                None => changes.missing.push(name.clone()),
+                Some(path) => {
+                    if path.metadata()?.len() != entry.size || sha256(&path)? != entry.sha256 {
+                        changes.modified.push(name.clone());
+                    }
+                }
+            }
+        }
+        changes.extra = found.into_keys().collect();
+        Ok(changes)
+    }
+}
+
+/// Regular files below `root` by their path relative to it.
+fn files(root: &Path) -> Result<BTreeMap<String, PathBuf>> {
+    let mut files = BTreeMap::new();
+    for entry in WalkDir::new(root) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        let name = relative
+            .components()
+            .map(|x| x.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        files.insert(name, entry.into_path());
+    }
+    Ok(files)
+}
+
+#[test]
+fn it_manifest() {
+    use temp_dir::TempDir;
+
+    let temp = TempDir::new().unwrap();
+    let root = temp.child("0.12.0");
+    create_dir_all(root.join("lib/std")).unwrap();
+    write(root.join("zig"), "zig").unwrap();
+    write(root.join("lib/std/std.zig"), "pub const x = 1;").unwrap();
+    write(root.join("lib/std/os.zig"), "pub const y = 2;").unwrap();
+
+    let manifest = Manifest::create(&root, "0.12.0", "00").unwrap();
+    assert_eq!(manifest.files.len(), 3);
+    assert!(manifest.files.contains_key("lib/std/std.zig"));
+    manifest.save(temp.path(), "0.12.0").unwrap();
+    let manifest = Manifest::load(temp.path(), "0.12.0").unwrap().unwrap();
+    assert!(manifest.verify(&root).unwrap().is_empty());
+
+    write(root.join("lib/std/std.zig"), "pub const x = 2;").unwrap();
+    std::fs::remove_file(root.join("lib/std/os.zig")).unwrap();
+    write(root.join("lib/extra.zig"), "").unwrap();
+    let changes = manifest.verify(&root).unwrap();
+    assert_eq!(changes.modified, ["lib/std/std.zig"]);
+    assert_eq!(changes.missing, ["lib/std/os.zig"]);
+    assert_eq!(changes.extra, ["lib/extra.zig"]);
+
+    Manifest::remove(temp.path(), "0.12.0").unwrap();
+    assert!(Manifest::load(temp.path(), "0.12.0").unwrap().is_none());
+}
@@ -0,0 +1,41 @@
+use std::env::current_dir;
+use std::fs::read_to_string;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use crate::error::{bail, Result};
+
+pub const PIN_FILE: &str = ".zig-version";
+
+/// Version spec standing for the one in the nearest `.zig-version` file.
+pub const PINNED: &str = "pinned";
+
+/// The version pinned by the nearest `.zig-version` file in the current directory or its parents.
+pub fn find() -> Result<Option<(PathBuf, String)>> {
+    let cwd = current_dir()?;
+    for dir in cwd.ancestors() {
+        let file = dir.join(PIN_FILE);
+        match read_to_string(&file) {
+            Ok(x) => return Ok(Some((file, x.trim().to_string()))),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(None)
+}
+
+/// Replaces [`PINNED`] with the version spec it refers to.
+pub fn expand(spec: &str) -> Result<String> {
+    if spec != PINNED {
+        return Ok(spec.to_string());
+    }
+    match find()? {
+        Some((_, x)) => Ok(x),
+        None => bail!(
+            NotFound,
+            "No {} file found in {:?} or its parents",
+            PIN_FILE,
+            current_dir()?
+        ),
+    }
+}
@@ -0,0 +1,112 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use indicatif::HumanBytes;
+use serde::Serialize;
+
+use crate::error::ErrorKind;
+
+/// How progress is reported while resolving, downloading and installing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Progress {
+    /// Nothing is printed.
+    #[default]
+    Hidden,
+    /// Status lines and progress bars redrawn on stderr, for terminals.
+    Bar,
+    /// Status lines and periodic plain-text updates on stderr, for CI logs.
+    Plain,
+    /// One JSON event per line on stderr, for editors and other frontends.
+    Json,
+}
+
+/// A step of an install, as emitted by [`Progress::Json`]. Every event is an object with an
+/// `event` field naming its variant in snake case.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A human readable status line.
+    Message { text: &'a str },
+    /// A version spec was looked up in the download index.
+    Resolve { spec: &'a str, version: &'a str },
+    /// Bytes of a tarball downloaded so far, with the total when the server sent one.
+    Download {
+        url: &'a str,
+        bytes: u64,
+        total: Option<u64>,
+    },
+    /// A tarball is being checked against its published SHA-256.
+    Verify { version: &'a str },
+    /// Files extracted from a tarball so far.
+    Extract { files: u64 },
+    /// `zig` and its drop-ins were linked into a directory.
+    Link { dir: &'a Path },
+    /// The command finished.
+    Done,
+    /// The command failed, with the kind of error when it came from zman.
+    Error {
+        kind: Option<ErrorKind>,
+        message: String,
+    },
+}
+
+impl Progress {
+    pub fn emit(self, event: &Event) {
+        match (self, event) {
+            (Progress::Json, event) => {
+                if let Ok(line) = serde_json::to_string(event) {
+                    eprintln!("{}", line);
+                }
+            }
+            (Progress::Bar | Progress::Plain, Event::Message { text }) => eprintln!("{}", text),
+            (Progress::Plain, Event::Download { url, bytes, total }) => match total {
+                Some(total) => eprintln!(
+                    "Downloading {}: {} of {}",
+                    url,
+                    HumanBytes(*bytes),
+                    HumanBytes(*total)
+                ),
+                None => eprintln!("Downloading {}: {}", url, HumanBytes(*bytes)),
+            },
+            (Progress::Plain, Event::Extract { files }) => eprintln!("Extracted {} files", files),
+            _ => {}
+        }
+    }
+
+    pub fn message(self, text: &str) {
+        self.emit(&Event::Message { text });
+    }
+}
+
+/// Limits how often repeated events like download progress are emitted.
+pub(crate) struct Ticker {
+    every: Duration,
+    last: Option<(Instant, u64)>,
+}
+
+impl Ticker {
+    pub fn new(progress: Progress) -> Ticker {
+        let every = match progress {
+            Progress::Plain => Duration::from_secs(5),
+            _ => Duration::from_millis(250),
+        };
+        Ticker { every, last: None }
+    }
+
+    /// Whether `value` should be emitted, which it is when enough time passed since the last one.
+    pub fn tick(&mut self, value: u64) -> bool {
+        let now = Instant::now();
+        if self.last.is_some_and(|(x, _)| now - x < self.every) {
+            return false;
+        }
+        self.last = Some((now, value));
+        true
+    }
+
+    /// Whether the final `value` should be emitted, which it is unless it was the last one.
+    pub fn finish(&mut self, value: u64) -> bool {
+        let done = self.last.is_some_and(|(_, x)| x == value);
+        self.last = Some((Instant::now(), value));
+        !done
+    }
+}
@@ -0,0 +1,72 @@
+use std::ffi::CString;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use indicatif::HumanBytes;
+
+use crate::error::{bail, Context, Error, ErrorKind, Result};
+
+/// Room kept for an unpacked tarball, as a multiple of its size. Zig tarballs unpack to around six
+/// times their size, and the rest covers releases that compress better and filesystems that
+/// allocate more per small file than the tarball records.
+pub const UNPACKED_RATIO: u64 = 8;
+
+/// Bytes available to unprivileged users on the filesystem holding `path`, which may not exist
+/// yet.
+fn free_space(path: &Path) -> Result<(u64, u64)> {
+    let existing = path
+        .ancestors()
+        .find(|x| x.exists())
+        .unwrap_or(Path::new("/"));
+    let device = existing.metadata()?.dev();
+    let c_path = CString::new(existing.as_os_str().as_bytes())
+        .map_err(|_| Error::new(ErrorKind::Invalid, format!("Invalid path {:?}", existing)))?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is a valid C string and `stat` is only read once statvfs succeeded
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Cannot check free space in {:?}", existing));
+    }
+    let stat = unsafe { stat.assume_init() };
+    #[allow(clippy::unnecessary_cast)]
+    Ok((device, stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+/// Fails unless every directory has room for the bytes it needs, adding up what directories on
+/// the same filesystem need.
+pub fn ensure_space(needed: &[(&Path, u64)]) -> Result<()> {
+    let mut filesystems: Vec<(u64, u64, u64, &Path)> = Vec::new();
+    for (dir, bytes) in needed {
+        let (device, free) = free_space(dir)?;
+        match filesystems.iter_mut().find(|x| x.0 == device) {
+            Some(x) => x.2 += bytes,
+            None => filesystems.push((device, free, *bytes, dir)),
+        }
+    }
+    for (_, free, bytes, dir) in filesystems {
+        if bytes > free {
+            bail!(
+                Io,
+                "Not enough disk space in {:?}: about {} is needed but only {} is free",
+                dir,
+                HumanBytes(bytes),
+                HumanBytes(free)
+            )
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn it_space() {
+    let (_, free) = free_space(Path::new("/tmp/zman/does/not/exist")).unwrap();
+    assert!(free > 0);
+    ensure_space(&[(Path::new("/tmp"), 1), (Path::new("/tmp/x"), 1)]).unwrap();
+    assert!(ensure_space(&[
+        (Path::new("/tmp"), u64::MAX / 2),
+        (Path::new("/tmp"), u64::MAX / 2)
+    ])
+    .is_err());
+}
@@ -0,0 +1,384 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_link, read_to_string, remove_file, rename, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cross::CrossFile;
+use crate::error::{bail, Context, Error, ErrorKind, Result};
+use crate::installs::installs;
+use crate::link::TargetDropins;
+
+const SCHEMA: u32 = 1;
+const STATE_FILE: &str = "state.json";
+
+/// Everything zman remembers about an install directory, stored as `state.json` inside it.
+#[derive(Serialize, Deserialize)]
+pub struct State {
+    pub schema: u32,
+    pub default: Option<DefaultVersion>,
+    pub keep: Vec<Keep>,
+    /// Install records by the name of their directory, like `0.12.0` or `master`.
+    pub installs: BTreeMap<String, InstallRecord>,
+    pub links: Vec<Link>,
+    /// Target drop-ins linked next to `zig` in every link directory.
+    #[serde(default)]
+    pub targets: Vec<TargetDropins>,
+    /// CMake and Meson files rewritten whenever the default version changes.
+    #[serde(default)]
+    pub cross_files: Vec<CrossFile>,
+    /// Names of versioned links like `zig-0.12` in the link directories, replaced whenever
+    /// versions are installed or removed.
+    #[serde(default)]
+    pub versioned: Vec<String>,
+    /// Defaults chosen by users of a shared install in place of the system default.
+    #[serde(default)]
+    pub user_defaults: Vec<UserDefault>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DefaultVersion {
+    /// Version as given by the user, like `latest`.
+    pub spec: String,
+    /// Directory name of the install it resolved to.
+    pub install: String,
+}
+
+/// A user's own default version on a shared install, linked into their own directory.
+#[derive(Serialize, Deserialize)]
+pub struct UserDefault {
+    pub uid: u32,
+    pub user: Option<String>,
+    pub spec: String,
+    pub install: String,
+    pub link: PathBuf,
+    pub dropins: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Keep {
+    pub version: String,
+    pub reason: Option<String>,
+    #[serde(with = "rfc3339")]
+    pub since: SystemTime,
+    /// After this the version can be cleaned again.
+    #[serde(default, with = "rfc3339::option")]
+    pub until: Option<SystemTime>,
+}
+
+impl Keep {
+    pub fn is_expired(&self) -> bool {
+        self.until.is_some_and(|x| x <= SystemTime::now())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InstallRecord {
+    /// Real version of the install, which differs from its name for `master`.
+    pub version: String,
+    #[serde(with = "rfc3339")]
+    pub installed: SystemTime,
+    /// SHA-256 of the tarball it was installed from, unknown for installs migrated from text files.
+    pub shasum: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Link {
+    pub dir: PathBuf,
+    pub dropins: bool,
+}
+
+impl State {
+    /// Reads the state of an install directory, migrating `default.txt` and `keep.txt` from older
+    /// versions of zman. `link_location` is where the default version was linked to by them.
+    pub fn load(install_location: &Path, link_location: &Path) -> Result<State> {
+        let file = install_location.join(STATE_FILE);
+        match read_to_string(&file) {
+            Ok(x) => {
+                let state: State = serde_json::from_str(&x)
+                    .with_context(|| format!("State file {:?} could not be parsed", file))?;
+                if state.schema > SCHEMA {
+                    bail!(
+                        Invalid,
+                        "State file {:?} was written by a newer version of zman",
+                        file
+                    )
+                }
+                Ok(state)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Self::migrate(install_location, link_location)
+            }
+            Err(e) => Err(e).with_context(|| format!("Cannot read state file {:?}", file)),
+        }
+    }
+
+    fn migrate(install_location: &Path, link_location: &Path) -> Result<State> {
+        let default_txt = install_location.join("default.txt");
+        let keep_txt = install_location.join("keep.txt");
+        let mut state = State::empty();
+        let default = read_optional(&default_txt)?;
+        let keep = read_optional(&keep_txt)?;
+        if default.is_none() && keep.is_none() {
+            return Ok(state);
+        }
+
+        let now = SystemTime::now();
+        state.record_installs(install_location)?;
+        if let Some(spec) = default {
+            let install = match linked_install(link_location) {
+                Some(x) if state.installs.contains_key(&x) => x,
+                _ => spec.clone(),
+            };
+            state.default = Some(DefaultVersion { spec, install });
+            state.add_link(link_location, link_location.join("zig-cc").try_exists()?);
+        }
+        for version in keep.iter().flat_map(|x| x.lines()) {
+            let version = version.trim();
+            if !version.is_empty() && !state.is_kept(version) {
+                state.keep.push(Keep {
+                    version: version.to_string(),
+                    reason: None,
+                    since: now,
+                    until: None,
+                });
+            }
+        }
+
+        state.save(install_location)?;
+        for file in [default_txt, keep_txt] {
+            match remove_file(&file) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(state)
+    }
+
+    /// Replaces a lost or unreadable state file, which is kept as `state.json.bak`, with one
+    /// recording the installs on disk and the version `zig` in `link_location` points at as the
+    /// default. Keeps and target drop-ins cannot be recovered.
+    pub fn rebuild(install_location: &Path, link_location: &Path) -> Result<State> {
+        let file = install_location.join(STATE_FILE);
+        match rename(&file, install_location.join(format!("{}.bak", STATE_FILE))) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let mut state = State::empty();
+        state.record_installs(install_location)?;
+        if let Some(install) = linked_install(link_location) {
+            if state.installs.contains_key(&install) {
+                state.default = Some(DefaultVersion {
+                    spec: install.clone(),
+                    install,
+                });
+                state.add_link(link_location, link_location.join("zig-cc").try_exists()?);
+            }
+        }
+        state.save(install_location)?;
+        Ok(state)
+    }
+
+    fn empty() -> State {
+        State {
+            schema: SCHEMA,
+            default: None,
+            keep: Vec::new(),
+            installs: BTreeMap::new(),
+            links: Vec::new(),
+            targets: Vec::new(),
+            cross_files: Vec::new(),
+            versioned: Vec::new(),
+            user_defaults: Vec::new(),
+        }
+    }
+
+    /// Records every install on disk, asking `zig` for the real version of `master`.
+    fn record_installs(&mut self, install_location: &Path) -> Result<()> {
+        for install in installs(install_location)? {
+            self.installs.insert(
+                install.name.clone(),
+                InstallRecord {
+                    version: install.resolved().unwrap_or_else(|| install.name.clone()),
+                    installed: install.installed,
+                    shasum: None,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Writes the state to a temporary file first so it is never left half written.
+    pub fn save(&self, install_location: &Path) -> Result<()> {
+        let file = install_location.join(STATE_FILE);
+        let temp = install_location.join(format!("{}.tmp", STATE_FILE));
+        create_dir_all(install_location)?;
+        let mut f =
+            File::create(&temp).with_context(|| format!("Cannot write state file {:?}", file))?;
+        f.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        f.sync_all()?;
+        rename(&temp, &file).with_context(|| format!("Cannot write state file {:?}", file))?;
+        Ok(())
+    }
+
+    pub fn default_install(&self) -> Option<&str> {
+        self.default.as_ref().map(|x| x.install.as_str())
+    }
+
+    /// The default chosen by the user with `uid` on a shared install.
+    pub fn user_default(&self, uid: u32) -> Option<&UserDefault> {
+        self.user_defaults.iter().find(|x| x.uid == uid)
+    }
+
+    pub fn kept(&self, version: &str) -> Option<&Keep> {
+        self.keep.iter().find(|x| x.version == version)
+    }
+
+    /// Whether a version is protected from cleaning by a keep that has not expired.
+    pub fn is_kept(&self, version: &str) -> bool {
+        self.kept(version).is_some_and(|x| !x.is_expired())
+    }
+
+    /// Protects a version from cleaning, replacing the reason and expiry of an existing keep.
+    pub fn keep(&mut self, version: &str, reason: Option<String>, until: Option<SystemTime>) {
+        self.unkeep(version);
+        self.keep.push(Keep {
+            version: version.to_string(),
+            reason,
+            since: SystemTime::now(),
+            until,
+        });
+    }
+
+    /// Returns whether the version was kept.
+    pub fn unkeep(&mut self, version: &str) -> bool {
+        let len = self.keep.len();
+        self.keep.retain(|x| x.version != version);
+        self.keep.len() != len
+    }
+
+    /// Forgets an install that was removed from disk, along with its protection from cleaning.
+    pub fn remove_install(&mut self, name: &str) {
+        self.installs.remove(name);
+        self.unkeep(name);
+    }
+
+    /// Records a link directory, replacing what was known about it.
+    pub fn add_link(&mut self, dir: &Path, dropins: bool) {
+        self.links.retain(|x| x.dir != dir);
+        self.links.push(Link {
+            dir: dir.to_path_buf(),
+            dropins,
+        });
+    }
+}
+
+/// Name of the install `zig` in a link directory points at.
+fn linked_install(link_location: &Path) -> Option<String> {
+    let target = read_link(link_location.join("zig")).ok()?;
+    Some(target.parent()?.file_name()?.to_string_lossy().to_string())
+}
+
+fn read_optional(file: &Path) -> Result<Option<String>> {
+    match read_to_string(file) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        x => Ok(Some(x?)),
+    }
+}
+
+mod rfc3339 {
+    use std::time::SystemTime;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&humantime::format_rfc3339_seconds(*time))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        humantime::parse_rfc3339(&time).map_err(D::Error::custom)
+    }
+
+    pub mod option {
+        use std::time::SystemTime;
+
+        use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            time: &Option<SystemTime>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match time {
+                Some(x) => super::serialize(x, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<SystemTime>, D::Error> {
+            Option::<String>::deserialize(deserializer)?
+                .map(|x| humantime::parse_rfc3339(&x).map_err(D::Error::custom))
+                .transpose()
+        }
+    }
+}
+
+/// Parses a date like `2026-12-31`, a timestamp like `2026-12-31T12:00:00Z` or a duration from now
+/// like `90d`.
+pub fn parse_until(until: &str) -> Result<SystemTime> {
+    if let Ok(x) = humantime::parse_rfc3339_weak(until) {
+        return Ok(x);
+    }
+    if let Ok(x) = humantime::parse_rfc3339(&format!("{}T00:00:00Z", until)) {
+        return Ok(x);
+    }
+    let duration = humantime::parse_duration(until).map_err(|_| {
+        Error::new(
+            ErrorKind::Invalid,
+            format!("Invalid date or duration {:?}", until),
+        )
+    })?;
+    match SystemTime::now().checked_add(duration) {
+        Some(x) => Ok(x),
+        None => bail!(Invalid, "Duration {:?} is too long", until),
+    }
+}
+
+#[test]
+fn it_state_migrate() {
+    use std::fs::{create_dir_all, write};
+    use temp_dir::TempDir;
+
+    let temp = TempDir::new().unwrap();
+    create_dir_all(temp.child("0.11.0")).unwrap();
+    create_dir_all(temp.child("0.12.0")).unwrap();
+    write(temp.child("default.txt"), "0.12.0").unwrap();
+    write(temp.child("keep.txt"), "0.11.0\n0.11.0").unwrap();
+
+    let state = State::load(temp.path(), &temp.child("bin")).unwrap();
+    assert_eq!(state.default_install(), Some("0.12.0"));
+    assert_eq!(state.keep.len(), 1);
+    assert_eq!(state.installs.len(), 2);
+    assert!(!temp.child("default.txt").exists());
+
+    let mut state = State::load(temp.path(), &temp.child("bin")).unwrap();
+    assert!(state.is_kept("0.11.0"));
+
+    assert!(parse_until("400000000000y").is_err());
+    state.keep("0.11.0", None, Some(parse_until("2000-01-01").unwrap()));
+    assert_eq!(state.keep.len(), 1);
+    assert!(!state.is_kept("0.11.0"));
+    assert!(state.unkeep("0.11.0"));
+    assert!(state.keep.is_empty());
+
+    write(temp.child("state.json"), "{").unwrap();
+    assert!(State::load(temp.path(), &temp.child("bin")).is_err());
+    let state = State::rebuild(temp.path(), &temp.child("bin")).unwrap();
+    assert_eq!(state.installs.len(), 2);
+    assert!(temp.child("state.json.bak").exists());
+    assert!(State::load(temp.path(), &temp.child("bin")).is_ok());
+}